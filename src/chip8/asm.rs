@@ -0,0 +1,871 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+// Address programs are loaded at
+const ROM_START: usize = 0x200;
+// Largest program that fits in (XO-CHIP) memory
+const MAX_ROM_SIZE: usize = 0x10000 - ROM_START;
+// Macros expanded within macros deeper than this are taken to be endlessly recursive
+const MAX_MACRO_DEPTH: usize = 64;
+
+// Error found assembling a program, located at a line and column (both starting at 1)
+#[derive(Debug, Clone, PartialEq)]
+pub struct AsmError {
+    pub line: usize,
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.line, self.column, self.message)
+    }
+}
+
+impl Error for AsmError {
+    fn description(&self) -> &str {
+        &self.message
+    }
+}
+
+// Word of the source, with its location
+#[derive(Debug, Clone, PartialEq)]
+struct Token {
+    text: String,
+    line: usize,
+    column: usize,
+    depth: usize,       // Number of nested macro expansions it comes from
+}
+
+impl Token {
+    fn error<T>(&self, message: String) -> Result<T, AsmError> {
+        Err(AsmError { line: self.line, column: self.column, message })
+    }
+}
+
+// Split the source in whitespace separated tokens, dropping '#' comments
+fn tokenize(source: &str) -> Vec<Token> {
+    let mut tokens = Vec::new();
+    for (n, line) in source.lines().enumerate() {
+        let mut start = None;
+        for (column, c) in line.chars().chain(Some(' ')).enumerate() {
+            if c == '#' && start.is_none() {
+                break;
+            }
+            match (c.is_whitespace(), start) {
+                (false, None) => start = Some(column),
+                (true, Some(first)) => {
+                    let text: String = line.chars().skip(first).take(column - first).collect();
+                    tokens.push(Token { text, line: n + 1, column: first + 1, depth: 0 });
+                    start = None;
+                },
+                _ => {},
+            }
+        }
+    }
+    tokens
+}
+
+// Parse a decimal, hexadecimal (0x) or binary (0b) number, possibly negative
+fn parse_number(text: &str) -> Option<i64> {
+    let (negative, digits) = match text.strip_prefix('-') { Some(digits) => (true, digits), None => (false, text) };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16)
+    } else if let Some(binary) = digits.strip_prefix("0b") {
+        i64::from_str_radix(binary, 2)
+    } else {
+        digits.parse::<i64>()
+    };
+    match value {
+        Ok(v) if negative => Some(-v),
+        Ok(v) => Some(v),
+        _ => None,
+    }
+}
+
+// Parse one of the 'v0' to 'vf' register names
+fn parse_register(text: &str) -> Option<u8> {
+    if text.len() == 2 && (text.starts_with('v') || text.starts_with('V')) {
+        u8::from_str_radix(&text[1..], 16).ok()
+    } else {
+        None
+    }
+}
+
+// Comparison in an 'if' or 'while' condition
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Comparison { Equal, NotEqual, Less, Greater, LessOrEqual, GreaterOrEqual, Key, NotKey }
+
+// Right side of a comparison
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operand { Register(u8), Value(u8) }
+
+// How a forward reference has to be patched once its label is defined
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FixupKind {
+    // 12-bit address in the low bits of the instruction
+    Address,
+    // 16-bit address following F000
+    Long,
+}
+
+struct Fixup {
+    offset: usize,
+    kind: FixupKind,
+    name: Token,
+}
+
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Token>,
+}
+
+// Assembler state, with the tokens yet to read in reverse order
+struct Assembler {
+    tokens: Vec<Token>,
+    last: Token,
+    rom: Vec<u8>,
+    pos: usize,                             // Offset from ROM_START where the next byte goes
+    labels: HashMap<String, u16>,
+    constants: HashMap<String, i64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    fixups: Vec<Fixup>,
+    loops: Vec<(u16, Vec<usize>)>,          // Start address and pending 'while' exits of open loops
+    blocks: Vec<(Token, usize, bool)>,      // Open 'if ... begin' blocks: token, pending jump, seen 'else'
+}
+
+// Assemble a program in Octo syntax into a ROM, to be loaded at 0x200
+pub fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+    let mut tokens = tokenize(source);
+    tokens.reverse();
+    let mut asm = Assembler {
+        tokens,
+        last: Token { text: String::new(), line: 1, column: 1, depth: 0 },
+        rom: Vec::new(),
+        pos: 0,
+        labels: HashMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        loops: Vec::new(),
+        blocks: Vec::new(),
+    };
+    // execution starts with a jump to 'main', unless it comes first
+    let start = asm.last.clone();
+    asm.fixups.push(Fixup { offset: 0, kind: FixupKind::Address, name: Token { text: String::from("main"), ..start } });
+    asm.emit(0x1000)?;
+    while !asm.tokens.is_empty() {
+        asm.statement()?;
+    }
+    asm.finish()
+}
+
+impl Assembler {
+
+    fn here(&self) -> u16 {
+        (ROM_START + self.pos) as u16
+    }
+
+    fn next(&mut self) -> Result<Token, AsmError> {
+        match self.tokens.pop() {
+            Some(token) => {
+                self.last = token.clone();
+                Ok(token)
+            },
+            None => self.last.error(String::from("unexpected end of file")),
+        }
+    }
+
+    fn peek_is(&self, text: &str) -> bool {
+        self.tokens.last().is_some_and(|t| t.text == text)
+    }
+
+    fn expect(&mut self, text: &str) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if token.text != text {
+            return token.error(format!("expected '{}', found '{}'", text, token.text));
+        }
+        Ok(())
+    }
+
+    fn emit_byte(&mut self, byte: u8) -> Result<(), AsmError> {
+        if self.pos >= MAX_ROM_SIZE {
+            return self.last.error(String::from("program doesn't fit in memory"));
+        }
+        if self.pos < self.rom.len() {
+            self.rom[self.pos] = byte;
+        } else {
+            self.rom.resize(self.pos, 0);
+            self.rom.push(byte);
+        }
+        self.pos += 1;
+        Ok(())
+    }
+
+    fn emit(&mut self, word: u16) -> Result<(), AsmError> {
+        self.emit_byte((word >> 8) as u8)?;
+        self.emit_byte(word as u8)
+    }
+
+    // Patch the 12-bit address of the instruction at 'offset'
+    fn patch(&mut self, offset: usize, addr: u16) {
+        self.rom[offset] = (self.rom[offset] & 0xF0) | ((addr >> 8) as u8 & 0xF);
+        self.rom[offset + 1] = addr as u8;
+    }
+
+    // Emit a jump to be patched later, returning its offset
+    fn emit_pending_jump(&mut self) -> Result<usize, AsmError> {
+        let offset = self.pos;
+        self.emit(0x1000)?;
+        Ok(offset)
+    }
+
+    fn define(&mut self, name: &Token) -> Result<(), AsmError> {
+        if parse_number(&name.text).is_some() || parse_register(&name.text).is_some() ||
+                self.labels.contains_key(&name.text) || self.constants.contains_key(&name.text) {
+            return name.error(format!("name '{}' already in use", name.text));
+        }
+        Ok(())
+    }
+
+    fn register(&mut self) -> Result<u8, AsmError> {
+        let token = self.next()?;
+        match parse_register(&token.text).or_else(|| self.aliases.get(&token.text).cloned()) {
+            Some(reg) => Ok(reg),
+            None => token.error(format!("expected a register, found '{}'", token.text)),
+        }
+    }
+
+    fn is_register(&self, token: &Token) -> bool {
+        parse_register(&token.text).is_some() || self.aliases.contains_key(&token.text)
+    }
+
+    // Value of a number or defined name
+    fn lookup(&self, token: &Token) -> Option<i64> {
+        parse_number(&token.text)
+            .or_else(|| self.constants.get(&token.text).cloned())
+            .or_else(|| self.labels.get(&token.text).map(|&addr| addr as i64))
+    }
+
+    fn value(&mut self, min: i64, max: i64) -> Result<i64, AsmError> {
+        let token = self.next()?;
+        match self.lookup(&token) {
+            Some(v) if v >= min && v <= max => Ok(v),
+            Some(v) => token.error(format!("value {} out of range {} to {}", v, min, max)),
+            None => token.error(format!("undefined name '{}'", token.text)),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, AsmError> {
+        self.value(-128, 255).map(|v| v as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, AsmError> {
+        self.value(0, 15).map(|v| v as u8)
+    }
+
+    // Address operand of the instruction about to be emitted, which may be a label defined later
+    fn address(&mut self, kind: FixupKind) -> Result<u16, AsmError> {
+        let max = if kind == FixupKind::Long { 0xFFFF } else { 0xFFF };
+        let token = self.next()?;
+        match self.lookup(&token) {
+            Some(v) if v >= 0 && v <= max => Ok(v as u16),
+            Some(v) => token.error(format!("address {} out of range", v)),
+            None => {
+                let offset = if kind == FixupKind::Long { self.pos + 2 } else { self.pos };
+                self.fixups.push(Fixup { offset, kind, name: token });
+                Ok(0)
+            },
+        }
+    }
+
+    fn statement(&mut self) -> Result<(), AsmError> {
+        let token = self.next()?;
+        if self.is_register(&token) {
+            self.tokens.push(token);
+            let x = self.register()?;
+            return self.register_statement(x);
+        }
+        match token.text.as_str() {
+            ":" => self.label(),
+            ":const" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                let value = self.value(-0x10000, 0xFFFF)?;
+                self.constants.insert(name.text, value);
+                Ok(())
+            },
+            ":alias" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                let reg = self.register()?;
+                self.aliases.insert(name.text, reg);
+                Ok(())
+            },
+            ":calc" => {
+                let name = self.next()?;
+                self.define(&name)?;
+                let value = self.braced_expression()?;
+                self.constants.insert(name.text, value);
+                Ok(())
+            },
+            ":macro" => self.macro_definition(),
+            ":byte" => {
+                if !self.peek_is("{") {
+                    let value = self.byte()?;
+                    return self.emit_byte(value);
+                }
+                let open = self.tokens.last().cloned().unwrap();
+                let value = self.braced_expression()?;
+                if !(-128..=255).contains(&value) {
+                    return open.error(format!("value {} out of range -128 to 255", value));
+                }
+                self.emit_byte(value as u8)
+            },
+            ":org" => {
+                // code can only go forward, over none that was already assembled
+                let addr = self.value(ROM_START as i64, 0xFFFF)?;
+                if addr < self.here() as i64 {
+                    return self.last.error(format!("':org' {:X} goes back over code assembled up to {:X}", addr, self.here()));
+                }
+                self.pos = addr as usize - ROM_START;
+                Ok(())
+            },
+            ":call" => {
+                let addr = self.address(FixupKind::Address)?;
+                self.emit(0x2000 | addr)
+            },
+            "clear" => self.emit(0x00E0),
+            "return" | ";" => self.emit(0x00EE),
+            "exit" => self.emit(0x00FD),
+            "lores" => self.emit(0x00FE),
+            "hires" => self.emit(0x00FF),
+            "scroll-down" => { let n = self.nibble()?; self.emit(0x00C0 | n as u16) },
+            "scroll-up" => { let n = self.nibble()?; self.emit(0x00D0 | n as u16) },
+            "scroll-right" => self.emit(0x00FB),
+            "scroll-left" => self.emit(0x00FC),
+            "native" => { let addr = self.address(FixupKind::Address)?; self.emit(addr) },
+            "jump" => { let addr = self.address(FixupKind::Address)?; self.emit(0x1000 | addr) },
+            "jump0" => { let addr = self.address(FixupKind::Address)?; self.emit(0xB000 | addr) },
+            "i" => self.i_statement(),
+            "delay" => { self.expect(":=")?; let x = self.register()?; self.emit_x(0xF015, x) },
+            "buzzer" => { self.expect(":=")?; let x = self.register()?; self.emit_x(0xF018, x) },
+            "pitch" => { self.expect(":=")?; let x = self.register()?; self.emit_x(0xF03A, x) },
+            "bcd" => { let x = self.register()?; self.emit_x(0xF033, x) },
+            "save" => self.load_store(0xF055, 0x5002),
+            "load" => self.load_store(0xF065, 0x5003),
+            "saveflags" => { let x = self.register()?; self.emit_x(0xF075, x) },
+            "loadflags" => { let x = self.register()?; self.emit_x(0xF085, x) },
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | (x as u16) << 8 | (y as u16) << 4 | n as u16)
+            },
+            "plane" => { let n = self.value(0, 3)?; self.emit(0xF001 | (n as u16) << 8) },
+            "audio" => self.emit(0xF002),
+            "if" => self.if_statement(&token),
+            "else" => self.else_statement(&token),
+            "end" => self.end_statement(&token),
+            "loop" => {
+                let start = self.here();
+                self.loops.push((start, Vec::new()));
+                Ok(())
+            },
+            "while" => {
+                if self.loops.is_empty() {
+                    return token.error(String::from("'while' outside of a loop"));
+                }
+                // leave the loop unless the condition holds
+                self.condition(true)?;
+                let exit = self.emit_pending_jump()?;
+                self.loops.last_mut().unwrap().1.push(exit);
+                Ok(())
+            },
+            "again" => {
+                let (start, exits) = match self.loops.pop() {
+                    Some(open_loop) => open_loop,
+                    None => return token.error(String::from("'again' without 'loop'")),
+                };
+                self.emit(0x1000 | start)?;
+                let here = self.here();
+                for exit in exits {
+                    self.patch(exit, here);
+                }
+                Ok(())
+            },
+            _ => {
+                if let Some(number) = parse_number(&token.text) {
+                    if !(-128..=255).contains(&number) {
+                        return token.error(format!("byte {} out of range", number));
+                    }
+                    return self.emit_byte(number as u8);
+                }
+                if self.macros.contains_key(&token.text) {
+                    return self.expand_macro(&token);
+                }
+                // any other name calls a subroutine
+                self.tokens.push(token);
+                let addr = self.address(FixupKind::Address)?;
+                self.emit(0x2000 | addr)
+            },
+        }
+    }
+
+    fn emit_x(&mut self, opcode: u16, x: u8) -> Result<(), AsmError> {
+        self.emit(opcode | (x as u16) << 8)
+    }
+
+    fn emit_xy(&mut self, opcode: u16, x: u8, y: u8) -> Result<(), AsmError> {
+        self.emit(opcode | (x as u16) << 8 | (y as u16) << 4)
+    }
+
+    fn label(&mut self) -> Result<(), AsmError> {
+        let name = self.next()?;
+        self.define(&name)?;
+        // no need to jump to 'main' if it comes right at the start
+        if name.text == "main" && self.pos == 2 && self.rom.len() == 2 {
+            self.rom.clear();
+            self.pos = 0;
+            self.fixups.retain(|f| f.name.text != "main");
+        }
+        let here = self.here();
+        self.labels.insert(name.text, here);
+        Ok(())
+    }
+
+    fn register_statement(&mut self, x: u8) -> Result<(), AsmError> {
+        let op = self.next()?;
+        let is_reg = self.tokens.last().is_some_and(|t| self.is_register(t));
+        match op.text.as_str() {
+            ":=" if self.peek_is("random") => {
+                self.next()?;
+                let kk = self.byte()?;
+                self.emit_x(0xC000 | kk as u16, x)
+            },
+            ":=" if self.peek_is("delay") => { self.next()?; self.emit_x(0xF007, x) },
+            ":=" if self.peek_is("key") => { self.next()?; self.emit_x(0xF00A, x) },
+            ":=" if is_reg => { let y = self.register()?; self.emit_xy(0x8000, x, y) },
+            ":=" => { let kk = self.byte()?; self.emit_x(0x6000 | kk as u16, x) },
+            "+=" if is_reg => { let y = self.register()?; self.emit_xy(0x8004, x, y) },
+            "+=" => { let kk = self.byte()?; self.emit_x(0x7000 | kk as u16, x) },
+            "-=" if is_reg => { let y = self.register()?; self.emit_xy(0x8005, x, y) },
+            "-=" => { let kk = self.byte()?; self.emit_x(0x7000 | kk.wrapping_neg() as u16, x) },
+            "=-" => { let y = self.register()?; self.emit_xy(0x8007, x, y) },
+            "|=" => { let y = self.register()?; self.emit_xy(0x8001, x, y) },
+            "&=" => { let y = self.register()?; self.emit_xy(0x8002, x, y) },
+            "^=" => { let y = self.register()?; self.emit_xy(0x8003, x, y) },
+            ">>=" => { let y = self.register()?; self.emit_xy(0x8006, x, y) },
+            "<<=" => { let y = self.register()?; self.emit_xy(0x800E, x, y) },
+            _ => op.error(format!("unknown operator '{}'", op.text)),
+        }
+    }
+
+    fn i_statement(&mut self) -> Result<(), AsmError> {
+        let op = self.next()?;
+        match op.text.as_str() {
+            ":=" if self.peek_is("long") => {
+                self.next()?;
+                let addr = self.address(FixupKind::Long)?;
+                self.emit(0xF000)?;
+                self.emit(addr)
+            },
+            ":=" if self.peek_is("hex") => { self.next()?; let x = self.register()?; self.emit_x(0xF029, x) },
+            ":=" if self.peek_is("bighex") => { self.next()?; let x = self.register()?; self.emit_x(0xF030, x) },
+            ":=" => { let addr = self.address(FixupKind::Address)?; self.emit(0xA000 | addr) },
+            "+=" => { let x = self.register()?; self.emit_x(0xF01E, x) },
+            _ => op.error(format!("unknown operator '{}'", op.text)),
+        }
+    }
+
+    // 'save vx' / 'save vx - vy' (and the same for 'load')
+    fn load_store(&mut self, opcode: u16, range_opcode: u16) -> Result<(), AsmError> {
+        let x = self.register()?;
+        if self.peek_is("-") {
+            self.next()?;
+            let y = self.register()?;
+            self.emit_xy(range_opcode, x, y)
+        } else {
+            self.emit_x(opcode, x)
+        }
+    }
+
+    // Parse a condition and emit the instructions skipping the next one when it is 'skip_when'
+    fn condition(&mut self, skip_when: bool) -> Result<(), AsmError> {
+        let x = self.register()?;
+        let op = self.next()?;
+        let comparison = match op.text.as_str() {
+            "==" => Comparison::Equal,
+            "!=" => Comparison::NotEqual,
+            "<" => Comparison::Less,
+            ">" => Comparison::Greater,
+            "<=" => Comparison::LessOrEqual,
+            ">=" => Comparison::GreaterOrEqual,
+            "key" => Comparison::Key,
+            "-key" => Comparison::NotKey,
+            _ => return op.error(format!("unknown comparison '{}'", op.text)),
+        };
+        if comparison == Comparison::Key || comparison == Comparison::NotKey {
+            let skip_if_pressed = (comparison == Comparison::Key) == skip_when;
+            return self.emit_x(if skip_if_pressed { 0xE09E } else { 0xE0A1 }, x);
+        }
+        let is_reg = self.tokens.last().is_some_and(|t| self.is_register(t));
+        let operand = if is_reg { Operand::Register(self.register()?) } else { Operand::Value(self.byte()?) };
+        // whether to skip when the operands are equal, for == and !=
+        let skip_if_equal = (comparison == Comparison::Equal) == skip_when;
+        match (comparison, operand) {
+            (Comparison::Equal, Operand::Value(kk)) | (Comparison::NotEqual, Operand::Value(kk)) =>
+                self.emit_x(if skip_if_equal { 0x3000 } else { 0x4000 } | kk as u16, x),
+            (Comparison::Equal, Operand::Register(y)) | (Comparison::NotEqual, Operand::Register(y)) =>
+                self.emit_xy(if skip_if_equal { 0x5000 } else { 0x9000 }, x, y),
+            _ => {
+                // compare through VF: VF := operand, then VF := x - VF or VF := VF - x,
+                // which leaves in VF whether there was no borrow
+                match operand {
+                    Operand::Register(y) => self.emit_xy(0x8F00, 0, y)?,
+                    Operand::Value(kk) => self.emit(0x6F00 | kk as u16)?,
+                }
+                let (subtract, holds_when_borrow) = match comparison {
+                    Comparison::Less => (0x8F07, true),              // VF := x - operand
+                    Comparison::GreaterOrEqual => (0x8F07, false),
+                    Comparison::Greater => (0x8F05, true),           // VF := operand - x
+                    _ => (0x8F05, false),
+                };
+                self.emit(subtract | (x as u16) << 4)?;
+                let skip_if_borrow = holds_when_borrow == skip_when;
+                self.emit(if skip_if_borrow { 0x3F00 } else { 0x4F00 })
+            },
+        }
+    }
+
+    fn if_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        // peek whether it is 'if ... then' or 'if ... begin', the keyword coming after the
+        // register, the comparison and, unless it checks a key, the operand
+        let keyword = match self.tokens.iter().rev().nth(1) {
+            Some(op) if op.text == "key" || op.text == "-key" => 2,
+            _ => 3,
+        };
+        let then = match self.tokens.iter().rev().nth(keyword).map(|t| t.text.as_str()) {
+            Some("then") => true,
+            Some("begin") => false,
+            _ => return token.error(String::from("'if' without 'then' or 'begin' after its condition")),
+        };
+        if then {
+            // skip the next statement unless the condition holds
+            self.condition(false)?;
+            self.expect("then")
+        } else {
+            // jump to 'else' or 'end' unless the condition holds
+            self.condition(true)?;
+            self.expect("begin")?;
+            let jump = self.emit_pending_jump()?;
+            self.blocks.push((token.clone(), jump, false));
+            Ok(())
+        }
+    }
+
+    fn else_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        match self.blocks.pop() {
+            Some((if_token, jump, false)) => {
+                let end_jump = self.emit_pending_jump()?;
+                let here = self.here();
+                self.patch(jump, here);
+                self.blocks.push((if_token, end_jump, true));
+                Ok(())
+            },
+            _ => token.error(String::from("'else' without 'if ... begin'")),
+        }
+    }
+
+    fn end_statement(&mut self, token: &Token) -> Result<(), AsmError> {
+        match self.blocks.pop() {
+            Some((_, jump, _)) => {
+                let here = self.here();
+                self.patch(jump, here);
+                Ok(())
+            },
+            None => token.error(String::from("'end' without 'if ... begin'")),
+        }
+    }
+
+    // Read tokens up to the matching '}', after an opening '{'
+    fn braced_tokens(&mut self) -> Result<Vec<Token>, AsmError> {
+        self.expect("{")?;
+        let mut depth = 1;
+        let mut body = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token.text == "{" {
+                depth += 1;
+            } else if token.text == "}" {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(body);
+                }
+            }
+            body.push(token);
+        }
+    }
+
+    fn macro_definition(&mut self) -> Result<(), AsmError> {
+        let name = self.next()?;
+        self.define(&name)?;
+        let mut params = Vec::new();
+        while !self.peek_is("{") {
+            params.push(self.next()?.text);
+        }
+        let body = self.braced_tokens()?;
+        self.macros.insert(name.text, Macro { params, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &Token) -> Result<(), AsmError> {
+        if name.depth >= MAX_MACRO_DEPTH {
+            return name.error(format!("macro '{}' nested too deeply, is it recursive?", name.text));
+        }
+        let params = self.macros[&name.text].params.clone();
+        let mut args = HashMap::new();
+        for param in params {
+            let arg = self.next()?;
+            args.insert(param, arg.text);
+        }
+        let expansion: Vec<Token> = self.macros[&name.text].body.iter()
+            .map(|t| Token { text: args.get(&t.text).unwrap_or(&t.text).clone(), depth: name.depth + 1, ..t.clone() })
+            .collect();
+        self.tokens.extend(expansion.into_iter().rev());
+        Ok(())
+    }
+
+    // Evaluate '{ expression }'. As in Octo, binary operators have no precedence and
+    // are evaluated from right to left, so parentheses are needed for anything else
+    fn braced_expression(&mut self) -> Result<i64, AsmError> {
+        let open = self.tokens.last().cloned();
+        let mut tokens = self.braced_tokens()?;
+        tokens.reverse();
+        let value = self.expression(&mut tokens)?;
+        match tokens.pop() {
+            Some(extra) => extra.error(format!("unexpected '{}' in expression", extra.text)),
+            None => match open {
+                Some(_) => Ok(value),
+                None => self.last.error(String::from("empty expression")),
+            },
+        }
+    }
+
+    fn expression(&self, tokens: &mut Vec<Token>) -> Result<i64, AsmError> {
+        let left = self.term(tokens)?;
+        let op = match tokens.last() {
+            Some(t) if t.text != ")" => t.clone(),
+            _ => return Ok(left),
+        };
+        tokens.pop();
+        let right = self.expression(tokens)?;
+        let value = match op.text.as_str() {
+            "+" => left.wrapping_add(right),
+            "-" => left.wrapping_sub(right),
+            "*" => left.wrapping_mul(right),
+            "/" | "%" if right == 0 => return op.error(String::from("division by zero")),
+            "/" => left.wrapping_div(right),
+            "%" => left.wrapping_rem(right),
+            "&" => left & right,
+            "|" => left | right,
+            "^" => left ^ right,
+            "<<" => left.wrapping_shl(right as u32),
+            ">>" => left.wrapping_shr(right as u32),
+            "min" => ::std::cmp::min(left, right),
+            "max" => ::std::cmp::max(left, right),
+            _ => return op.error(format!("unknown operator '{}'", op.text)),
+        };
+        Ok(value)
+    }
+
+    fn term(&self, tokens: &mut Vec<Token>) -> Result<i64, AsmError> {
+        let token = match tokens.pop() {
+            Some(token) => token,
+            None => return self.last.error(String::from("incomplete expression")),
+        };
+        match token.text.as_str() {
+            "(" => {
+                let value = self.expression(tokens)?;
+                match tokens.pop() {
+                    Some(ref t) if t.text == ")" => Ok(value),
+                    _ => token.error(String::from("unclosed '('")),
+                }
+            },
+            "-" => self.term(tokens).map(|v| v.wrapping_neg()),
+            "~" => self.term(tokens).map(|v| !v),
+            "!" => self.term(tokens).map(|v| if v == 0 { 1 } else { 0 }),
+            "HERE" => Ok(self.here() as i64),
+            _ => match self.lookup(&token) {
+                Some(value) => Ok(value),
+                None => token.error(format!("undefined name '{}'", token.text)),
+            },
+        }
+    }
+
+    // Check everything is closed and patch forward references
+    fn finish(mut self) -> Result<Vec<u8>, AsmError> {
+        if let Some((token, _, _)) = self.blocks.last() {
+            return token.error(String::from("'if ... begin' without 'end'"));
+        }
+        if !self.loops.is_empty() {
+            return self.last.error(String::from("'loop' without 'again'"));
+        }
+        let fixups: Vec<Fixup> = self.fixups.drain(..).collect();
+        for fixup in fixups {
+            let addr = match self.labels.get(&fixup.name.text) {
+                Some(&addr) => addr,
+                None if fixup.name.text == "main" && fixup.offset == 0 =>
+                    return fixup.name.error(String::from("missing 'main' label")),
+                None => return fixup.name.error(format!("undefined name '{}'", fixup.name.text)),
+            };
+            match fixup.kind {
+                FixupKind::Address if addr > 0xFFF =>
+                    return fixup.name.error(format!("address of '{}' out of range, use 'i := long'", fixup.name.text)),
+                FixupKind::Address => self.patch(fixup.offset, addr),
+                FixupKind::Long => {
+                    self.rom[fixup.offset] = (addr >> 8) as u8;
+                    self.rom[fixup.offset + 1] = addr as u8;
+                },
+            }
+        }
+        Ok(self.rom)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chip8::Chip8State;
+
+    fn error_at(source: &str) -> (usize, usize) {
+        let err = assemble(source).unwrap_err();
+        (err.line, err.column)
+    }
+
+    #[test]
+    fn instructions() {
+        let rom = assemble(": main\n clear v3 := 0x2A v3 += v4 i := 0x300 sprite v1 v2 5 return").unwrap();
+        assert_eq!(rom, [0x00, 0xE0, 0x63, 0x2A, 0x83, 0x44, 0xA3, 0x00, 0xD1, 0x25, 0x00, 0xEE]);
+    }
+
+    #[test]
+    fn jump_to_main() {
+        let rom = assemble(": sub return\n: main sub").unwrap();
+        assert_eq!(rom, [0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]);
+    }
+
+    #[test]
+    fn forward_labels() {
+        let rom = assemble(": main jump later i := long data : later exit : data 0xFF").unwrap();
+        assert_eq!(rom, [0x12, 0x06, 0xF0, 0x00, 0x02, 0x08, 0x00, 0xFD, 0xFF]);
+    }
+
+    #[test]
+    fn const_alias_and_calc() {
+        let rom = assemble(":const SPEED 3 :alias px v5 :calc DOUBLE { SPEED * 2 + 1 }\n\
+                            : main px := SPEED px += DOUBLE").unwrap();
+        assert_eq!(rom, [0x65, 0x03, 0x75, 0x09]);
+    }
+
+    #[test]
+    fn calc_goes_from_right_to_left() {
+        let rom = assemble(":calc A { 10 - 4 - 1 } :calc B { ( 10 - 4 ) - 1 } : main :byte A :byte { B }").unwrap();
+        assert_eq!(rom, [7, 5]);
+    }
+
+    #[test]
+    fn macros() {
+        let rom = assemble(":macro swap A B { vf := A A := B B := vf }\n: main swap v1 v2").unwrap();
+        assert_eq!(rom, [0x8F, 0x10, 0x81, 0x20, 0x82, 0xF0]);
+    }
+
+    #[test]
+    fn recursive_macros_fail() {
+        assert_eq!(error_at(":macro m { m }\n: main m"), (1, 12));
+        let err = assemble(":macro a { b } :macro b { a }\n: main a").unwrap_err();
+        assert_eq!(err.line, 1);
+        assert!(err.message.contains("recursive"));
+    }
+
+    #[test]
+    fn calc_wraps_around() {
+        let rom = assemble(":calc MIN { - ( 1 << 63 ) } :calc Q { MIN / -1 } :calc R { MIN % -1 }\n\
+                            : main :byte { Q >> 56 } :byte { R }").unwrap();
+        assert_eq!(rom, [0x80, 0]);
+    }
+
+    #[test]
+    fn if_then() {
+        let rom = assemble(": main if v1 == 3 then v2 := 1 if v1 key then v2 := 2").unwrap();
+        assert_eq!(rom, [0x41, 0x03, 0x62, 0x01, 0xE1, 0xA1, 0x62, 0x02]);
+    }
+
+    #[test]
+    fn then_or_begin_must_follow_the_condition() {
+        assert_eq!(error_at(": main if v1 == 3 v2 := 1 then"), (1, 8));
+        assert_eq!(error_at(": main loop if v1 key again begin end"), (1, 13));
+    }
+
+    #[test]
+    fn if_begin_else_end() {
+        let rom = assemble(": main if v1 != v2 begin v3 := 1 else v3 := 2 end exit").unwrap();
+        assert_eq!(rom, [0x91, 0x20, 0x12, 0x08, 0x63, 0x01, 0x12, 0x0A, 0x63, 0x02, 0x00, 0xFD]);
+    }
+
+    #[test]
+    fn loop_while_again() {
+        let rom = assemble(": main loop v0 += 1 while v0 != 10 again exit").unwrap();
+        assert_eq!(rom, [0x70, 0x01, 0x40, 0x0A, 0x12, 0x08, 0x12, 0x00, 0x00, 0xFD]);
+    }
+
+    #[test]
+    fn comparisons_run_as_expected() {
+        let source = ": main v0 := 5 v1 := 7\n\
+                      if v0 < v1 then va := 1\n\
+                      if v0 > v1 then vb := 1\n\
+                      if v1 >= 6 then vc := 1\n\
+                      if v1 <= 6 then vd := 1\n\
+                      exit";
+        let mut state = Chip8State::new().load(&assemble(source).unwrap());
+        while !state.is_halted() {
+            state = state.exec_instruction().unwrap();
+        }
+        assert_eq!(&state.reg[0xA..0xE], &[1, 0, 1, 0]);
+    }
+
+    #[test]
+    fn byte_expressions_are_range_checked() {
+        let rom = assemble(": main :byte { 255 } :byte { -128 }").unwrap();
+        assert_eq!(rom, [0xFF, 0x80]);
+        assert_eq!(error_at(": main\n :byte { 256 }"), (2, 8));
+        assert_eq!(error_at(": main\n :byte { -129 }"), (2, 8));
+    }
+
+    #[test]
+    fn org_only_goes_forward() {
+        let rom = assemble(": main exit :org 0x206 :byte 1").unwrap();
+        assert_eq!(rom, [0x00, 0xFD, 0, 0, 0, 0, 1]);
+        assert_eq!(error_at(": main exit exit :org 0x202 :byte 1"), (1, 23));
+        assert!(assemble(": main exit :org 0x202 :byte 1").is_ok());
+    }
+
+    #[test]
+    fn comments_are_ignored() {
+        let rom = assemble("# a program\n: main # starts here\n  exit # bye").unwrap();
+        assert_eq!(rom, [0x00, 0xFD]);
+    }
+
+    #[test]
+    fn errors_have_line_and_column() {
+        assert_eq!(error_at(": main\n  v1 := 300"), (2, 9));
+        assert_eq!(error_at(": main\n  jump nowhere"), (2, 8));
+        assert_eq!(error_at(": main\n v1 <- v2"), (2, 5));
+        assert_eq!(error_at(": main\n: main"), (2, 3));
+        assert_eq!(error_at("\n  else"), (2, 3));
+        assert_eq!(error_at("exit"), (1, 1));
+    }
+}
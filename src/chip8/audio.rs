@@ -0,0 +1,263 @@
+use std::io;
+use std::io::{Seek, SeekFrom, Write};
+use byteorder::{LittleEndian, WriteBytesExt};
+use super::instructions_in_frame;
+
+// Samples per second of the generated sound: 16-bit signed, mono
+pub const SAMPLE_RATE: u32 = 44100;
+// Rate of the sound timer, and of the frames sinks are told about
+const TIMER_RATE: u32 = 60;
+
+// Receives the state of the sound timer, the machine beeping while it's active
+pub trait AudioSink {
+    // The sound timer became active or inactive, at the start of the current frame
+    fn set_active(&mut self, active: bool) -> io::Result<()>;
+    // The current frame is over
+    fn end_frame(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+    // No more frames will come
+    fn finish(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+// Pitch and loudness of the beep
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Beep {
+    pub frequency: u32,     // In hertzs, up to half the sample rate
+    pub volume: u8,         // From 0 to 100
+}
+
+impl Default for Beep {
+    fn default() -> Beep {
+        Beep { frequency: 440, volume: 50 }
+    }
+}
+
+// Square wave for a beep, turned on and off frame by frame
+struct SquareWave {
+    beep: Beep,
+    active: bool,
+    frame: u64,     // Frames generated so far
+    phase: u32,     // Position in the current period, a period being SAMPLE_RATE long
+}
+
+impl SquareWave {
+    fn new(beep: Beep) -> SquareWave {
+        SquareWave { beep, active: false, frame: 0, phase: 0 }
+    }
+
+    // Each beep starts at the start of a period
+    fn set_active(&mut self, active: bool) {
+        if active && !self.active {
+            self.phase = 0;
+        }
+        self.active = active;
+    }
+
+    // Samples for the next frame: the wave if active, silence otherwise. Frames don't hold a
+    // whole number of samples, so they're spread like instructions are
+    fn next_frame(&mut self) -> Vec<i16> {
+        let count = instructions_in_frame(SAMPLE_RATE, TIMER_RATE, self.frame);
+        self.frame += 1;
+        let amplitude = (i16::MAX as u32 * self.beep.volume as u32 / 100) as i16;
+        let mut samples = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            samples.push(match (self.active, self.phase < SAMPLE_RATE / 2) {
+                (false, _) => 0,
+                (true, true) => amplitude,
+                (true, false) => -amplitude,
+            });
+            self.phase = (self.phase + self.beep.frequency) % SAMPLE_RATE;
+        }
+        samples
+    }
+}
+
+fn write_samples(mut w: impl Write, samples: &[i16]) -> io::Result<()> {
+    for &sample in samples {
+        w.write_i16::<LittleEndian>(sample)?;
+    }
+    Ok(())
+}
+
+// Writes the sound to a WAV file. Sizes in the header are filled in by 'finish'
+pub struct WavSink<W: Write + Seek> {
+    out: W,
+    wave: SquareWave,
+    data_size: u32,     // Bytes of samples written
+}
+
+impl<W: Write + Seek> WavSink<W> {
+    pub fn new(mut out: W, beep: Beep) -> io::Result<WavSink<W>> {
+        out.write_all(b"RIFF")?;
+        out.write_u32::<LittleEndian>(0)?;
+        out.write_all(b"WAVEfmt ")?;
+        out.write_u32::<LittleEndian>(16)?;
+        out.write_u16::<LittleEndian>(1)?;                  // PCM
+        out.write_u16::<LittleEndian>(1)?;                  // mono
+        out.write_u32::<LittleEndian>(SAMPLE_RATE)?;
+        out.write_u32::<LittleEndian>(SAMPLE_RATE * 2)?;    // bytes per second
+        out.write_u16::<LittleEndian>(2)?;                  // bytes per sample
+        out.write_u16::<LittleEndian>(16)?;                 // bits per sample
+        out.write_all(b"data")?;
+        out.write_u32::<LittleEndian>(0)?;
+        Ok(WavSink { out, wave: SquareWave::new(beep), data_size: 0 })
+    }
+
+    // The output, once finished
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
+
+impl<W: Write + Seek> AudioSink for WavSink<W> {
+    fn set_active(&mut self, active: bool) -> io::Result<()> {
+        self.wave.set_active(active);
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> io::Result<()> {
+        let samples = self.wave.next_frame();
+        self.data_size += samples.len() as u32 * 2;
+        write_samples(&mut self.out, &samples)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.out.seek(SeekFrom::Start(4))?;
+        self.out.write_u32::<LittleEndian>(36 + self.data_size)?;
+        self.out.seek(SeekFrom::Start(40))?;
+        self.out.write_u32::<LittleEndian>(self.data_size)?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()
+    }
+}
+
+// Streams the sound as raw samples, to be piped into a player
+// (e.g. 'aplay -f S16_LE -r 44100' or 'ffplay -f s16le -ar 44100 -ac 1 -')
+pub struct PcmSink<W: Write> {
+    out: W,
+    wave: SquareWave,
+}
+
+impl<W: Write> PcmSink<W> {
+    pub fn new(out: W, beep: Beep) -> PcmSink<W> {
+        PcmSink { out, wave: SquareWave::new(beep) }
+    }
+}
+
+impl<W: Write> AudioSink for PcmSink<W> {
+    fn set_active(&mut self, active: bool) -> io::Result<()> {
+        self.wave.set_active(active);
+        Ok(())
+    }
+
+    fn end_frame(&mut self) -> io::Result<()> {
+        let samples = self.wave.next_frame();
+        write_samples(&mut self.out, &samples)?;
+        self.out.flush()
+    }
+}
+
+// Rings the terminal bell each time the sound starts. The terminal decides what it
+// sounds like, so the beep settings don't apply
+pub struct BellSink<W: Write> {
+    out: W,
+}
+
+impl<W: Write> BellSink<W> {
+    pub fn new(out: W) -> BellSink<W> {
+        BellSink { out }
+    }
+}
+
+impl<W: Write> AudioSink for BellSink<W> {
+    fn set_active(&mut self, active: bool) -> io::Result<()> {
+        if active {
+            self.out.write_all(b"\x07")?;
+            self.out.flush()?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+    use byteorder::{ByteOrder, LittleEndian};
+
+    fn samples(bytes: &[u8]) -> Vec<i16> {
+        bytes.chunks(2).map(LittleEndian::read_i16).collect()
+    }
+
+    #[test]
+    fn square_wave() {
+        let mut wave = SquareWave::new(Beep { frequency: 4410, volume: 100 });
+        assert!(wave.next_frame().iter().all(|&s| s == 0));
+        wave.set_active(true);
+        let frame = wave.next_frame();
+        assert_eq!(frame.len(), 735);
+        // 10 samples per period, half of them high
+        assert_eq!(&frame[..10], &[32767, 32767, 32767, 32767, 32767, -32767, -32767, -32767, -32767, -32767]);
+        assert_eq!(frame[10], 32767);
+    }
+
+    #[test]
+    fn volume() {
+        let mut wave = SquareWave::new(Beep { frequency: 440, volume: 50 });
+        wave.set_active(true);
+        assert_eq!(wave.next_frame()[0], 16383);
+        wave.beep.volume = 0;
+        assert!(wave.next_frame().iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn wav_file() {
+        let mut sink = WavSink::new(Cursor::new(Vec::new()), Beep::default()).unwrap();
+        sink.end_frame().unwrap();
+        sink.set_active(true).unwrap();
+        sink.end_frame().unwrap();
+        sink.finish().unwrap();
+        let data = sink.into_inner().into_inner();
+        assert_eq!(&data[0..4], b"RIFF");
+        assert_eq!(LittleEndian::read_u32(&data[4..]), 36 + 2 * 735 * 2);
+        assert_eq!(&data[8..16], b"WAVEfmt ");
+        assert_eq!(LittleEndian::read_u32(&data[24..]), SAMPLE_RATE);
+        assert_eq!(LittleEndian::read_u32(&data[40..]), 2 * 735 * 2);
+        let samples = samples(&data[44..]);
+        assert_eq!(samples.len(), 2 * 735);
+        assert!(samples[..735].iter().all(|&s| s == 0));
+        assert!(samples[735..].iter().all(|&s| s != 0));
+    }
+
+    #[test]
+    fn pcm_stream() {
+        let mut out = Vec::new();
+        {
+            let mut sink = PcmSink::new(&mut out, Beep::default());
+            sink.set_active(true).unwrap();
+            sink.end_frame().unwrap();
+            sink.set_active(false).unwrap();
+            sink.end_frame().unwrap();
+        }
+        let samples = samples(&out);
+        assert_eq!(samples.len(), 2 * 735);
+        assert!(samples[..735].iter().all(|&s| s != 0));
+        assert!(samples[735..].iter().all(|&s| s == 0));
+    }
+
+    #[test]
+    fn bell() {
+        let mut out = Vec::new();
+        {
+            let mut sink = BellSink::new(&mut out);
+            sink.set_active(true).unwrap();
+            sink.end_frame().unwrap();
+            sink.set_active(false).unwrap();
+            sink.set_active(true).unwrap();
+        }
+        assert_eq!(out, b"\x07\x07");
+    }
+}
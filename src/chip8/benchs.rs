@@ -17,3 +17,57 @@ fn instruction_execution_bench(b: &mut Bencher) {
     ch8state.reg[0] = 0x83;
     b.iter(|| ch8state.exec_instruction());
 }
+
+// A program keeping busy with arithmetic, memory and drawing, looping forever
+fn busy_rom() -> Vec<u8> {
+    asm::assemble("
+        : main
+            i := sprite
+            vc := 31
+            vd := 15
+            loop
+                v0 += 3
+                v1 += 5
+                v2 := v0
+                v2 ^= v1
+                v2 >>= v2
+                va := v0
+                va &= vc
+                vb := v1
+                vb &= vd
+                sprite va vb 4
+                save v3
+                load v3
+                bcd v2
+            again
+        : sprite
+            0x3C 0x42 0x42 0x3C
+    ").unwrap()
+}
+
+// Instructions run in each iteration of the whole-ROM benchmarks
+const ROM_BENCH_INSTRUCTIONS: usize = 10000;
+
+#[bench]
+fn rom_throughput_exec_instruction_bench(b: &mut Bencher) {
+    let ch8state = Chip8State::new().load(&busy_rom());
+    b.iter(|| {
+        let mut state = ch8state.clone();
+        for _ in 0..ROM_BENCH_INSTRUCTIONS {
+            state = state.exec_instruction().unwrap();
+        }
+        state
+    });
+}
+
+#[bench]
+fn rom_throughput_step_bench(b: &mut Bencher) {
+    let ch8state = Chip8State::new().load(&busy_rom());
+    b.iter(|| {
+        let mut state = ch8state.clone();
+        for _ in 0..ROM_BENCH_INSTRUCTIONS {
+            state.step().unwrap();
+        }
+        state
+    });
+}
@@ -0,0 +1,235 @@
+use super::Chip8State;
+
+// What the debugger lets the machine do next
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Mode {
+    Paused,
+    // Execute a single instruction, then pause
+    Step,
+    // Run until a breakpoint is hit
+    Running,
+    // Run until the PC gets to 'addr' with the given SP, i.e. once a called subroutine returns
+    StepOver { addr: u16, sp: u8 },
+    // Run until the PC gets to the address
+    RunTo(u16),
+}
+
+// Interactive debugger, controlled with keys typed by the user
+#[derive(Clone, Debug)]
+pub struct Debugger {
+    mode: Mode,
+    breakpoints: Vec<u16>,
+    resume_pc: Option<u16>,         // Address execution was resumed from, where breakpoints are ignored once
+    address_input: Option<String>,  // Address being typed for 'run to', if any
+}
+
+impl Debugger {
+
+    // Create a debugger, initially paused
+    pub fn new(breakpoints: &[u16]) -> Debugger {
+        Debugger {
+            mode: Mode::Paused,
+            breakpoints: breakpoints.to_vec(),
+            resume_pc: None,
+            address_input: None,
+        }
+    }
+
+    pub fn is_paused(&self) -> bool {
+        self.mode == Mode::Paused
+    }
+
+    pub fn pause(&mut self) {
+        self.mode = Mode::Paused;
+    }
+
+    pub fn is_breakpoint(&self, addr: u16) -> bool {
+        self.breakpoints.contains(&addr)
+    }
+
+    // The 'run to' address typed so far, if being typed
+    pub fn address_input(&self) -> Option<&str> {
+        self.address_input.as_deref()
+    }
+
+    // Short description of what the debugger is doing
+    pub fn status(&self) -> String {
+        match self.mode {
+            Mode::Paused => String::from("PAUSED"),
+            Mode::Step => String::from("STEPPING"),
+            Mode::Running => String::from("RUNNING"),
+            Mode::StepOver { addr, .. } => format!("RUNNING TO {:03X}", addr),
+            Mode::RunTo(addr) => format!("RUNNING TO {:03X}", addr),
+        }
+    }
+
+    // Handle a key typed by the user. Returns false if it isn't a debugger command,
+    // so it can be passed to the program (commands avoid hex digits, used by the keypad)
+    pub fn key_pressed(&mut self, key: char, state: &Chip8State) -> bool {
+        // while typing an address, take every key: Enter confirms, anything but hex digits cancels
+        if let Some(mut input) = self.address_input.take() {
+            match key {
+                '\n' | '\r' => {
+                    if let Ok(addr) = u16::from_str_radix(&input, 16) {
+                        self.resume(state, Mode::RunTo(addr));
+                    }
+                },
+                c if c.is_ascii_hexdigit() && input.len() < 4 => {
+                    input.push(c);
+                    self.address_input = Some(input);
+                },
+                _ => {}
+            }
+            return true;
+        }
+        match key {
+            // step
+            'n' => self.resume(state, Mode::Step),
+            // step over subroutine calls
+            'o' => {
+                let mode = match state.word_at(state.pc) {
+                    Some(word) if word & 0xF000 == 0x2000 => Mode::StepOver { addr: state.pc.wrapping_add(2), sp: state.sp },
+                    _ => Mode::Step,
+                };
+                self.resume(state, mode);
+            },
+            // continue
+            'l' => self.resume(state, Mode::Running),
+            // pause
+            'p' => self.pause(),
+            // toggle breakpoint at PC
+            't' => self.toggle_breakpoint(state.pc),
+            // run to address
+            'g' => self.address_input = Some(String::new()),
+            _ => return false,
+        }
+        true
+    }
+
+    fn resume(&mut self, state: &Chip8State, mode: Mode) {
+        self.mode = mode;
+        self.resume_pc = Some(state.pc);
+    }
+
+    fn toggle_breakpoint(&mut self, addr: u16) {
+        if self.is_breakpoint(addr) {
+            self.breakpoints.retain(|&bp| bp != addr);
+        } else {
+            self.breakpoints.push(addr);
+        }
+    }
+
+    // To be called before executing each instruction: whether it can be executed.
+    // Pauses if the instruction is where the debugger has to stop
+    pub fn may_execute(&mut self, state: &Chip8State) -> bool {
+        let pc = state.pc;
+        let resuming = self.resume_pc.take() == Some(pc);
+        let stop = match self.mode {
+            Mode::Paused => return false,
+            Mode::Step => {
+                self.pause();
+                return true;
+            },
+            Mode::Running => false,
+            Mode::StepOver { addr, sp } => pc == addr && state.sp == sp,
+            Mode::RunTo(addr) => pc == addr,
+        };
+        if !resuming && (stop || self.is_breakpoint(pc)) {
+            self.pause();
+            return false;
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Run the machine for at most 'n' instructions, as the debugger allows
+    fn run(debugger: &mut Debugger, mut state: Chip8State, n: usize) -> Chip8State {
+        for _ in 0..n {
+            if debugger.may_execute(&state) {
+                state = state.exec_instruction().unwrap();
+            }
+        }
+        state
+    }
+
+    #[test]
+    fn starts_paused() {
+        let mut debugger = Debugger::new(&[]);
+        let state = Chip8State::new().load(&[0x12, 0x00]);
+        assert!(debugger.is_paused());
+        assert!(!debugger.may_execute(&state));
+    }
+
+    #[test]
+    fn step() {
+        let mut debugger = Debugger::new(&[]);
+        let mut state = Chip8State::new().load(&[0x60, 0x01, 0x61, 0x02, 0x12, 0x00]);
+        assert!(debugger.key_pressed('n', &state));
+        state = run(&mut debugger, state, 10);
+        assert_eq!(state.pc, 0x202);
+        assert!(debugger.is_paused());
+    }
+
+    #[test]
+    fn continue_until_breakpoint() {
+        let mut debugger = Debugger::new(&[0x204]);
+        let mut state = Chip8State::new().load(&[0x60, 0x01, 0x61, 0x02, 0x12, 0x00]);
+        debugger.key_pressed('l', &state);
+        state = run(&mut debugger, state, 10);
+        assert_eq!(state.pc, 0x204);
+        assert!(debugger.is_paused());
+        // resuming from the breakpoint goes on until hitting it again
+        debugger.key_pressed('l', &state);
+        state = run(&mut debugger, state, 1);
+        assert_eq!(state.pc, 0x200);
+        assert!(!debugger.is_paused());
+    }
+
+    #[test]
+    fn toggle_breakpoint() {
+        let mut debugger = Debugger::new(&[]);
+        let state = Chip8State::new();
+        debugger.key_pressed('t', &state);
+        assert!(debugger.is_breakpoint(0x200));
+        debugger.key_pressed('t', &state);
+        assert!(!debugger.is_breakpoint(0x200));
+    }
+
+    #[test]
+    fn step_over_call() {
+        let mut debugger = Debugger::new(&[]);
+        // 200: call 206; 202: jump 202; 206: v0 := 1; 208: v1 := 2; 20A: return
+        let mut state = Chip8State::new().load(&[0x22, 0x06, 0x12, 0x02, 0x00, 0x00,
+                                                  0x60, 0x01, 0x61, 0x02, 0x00, 0xEE]);
+        debugger.key_pressed('o', &state);
+        state = run(&mut debugger, state, 10);
+        assert_eq!(state.pc, 0x202);
+        assert_eq!(state.reg[1], 2);
+        assert!(debugger.is_paused());
+    }
+
+    #[test]
+    fn run_to_address() {
+        let mut debugger = Debugger::new(&[]);
+        let mut state = Chip8State::new().load(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x00]);
+        for key in "g204\n".chars() {
+            assert!(debugger.key_pressed(key, &state));
+        }
+        state = run(&mut debugger, state, 10);
+        assert_eq!(state.pc, 0x204);
+        assert!(debugger.is_paused());
+    }
+
+    #[test]
+    fn other_keys_go_to_the_program() {
+        let mut debugger = Debugger::new(&[]);
+        let state = Chip8State::new();
+        assert!(!debugger.key_pressed('b', &state));
+        assert!(!debugger.key_pressed('c', &state));
+        assert!(!debugger.key_pressed('5', &state));
+    }
+}
@@ -0,0 +1,275 @@
+use std::collections::BTreeMap;
+use byteorder::{BigEndian, ByteOrder};
+use super::{Instruction, Quirks};
+
+// Address programs are loaded at
+const ROM_START: u16 = 0x200;
+
+// Largest ROM that fits in the 64K address space of XO-CHIP
+pub const MAX_ROM_SIZE: usize = 0x10000 - ROM_START as usize;
+
+// Human readable mnemonic for an instruction word, in the style of Cowgod's reference
+pub fn mnemonic(word: u16, quirks: &Quirks) -> String {
+    match Instruction::decode(word) {
+        Ok(instruction) => instruction.format_with(quirks, |addr| format!("{:03X}", addr)),
+        Err(_) => format!("DW {:04X}", word),
+    }
+}
+
+// Why an address gets a label, in order of precedence
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum LabelKind {
+    Data,
+    Sprite,
+    Jump,
+    Subroutine,
+}
+
+// What is known about a ROM after following its control flow from the entry point
+struct Analysis<'a> {
+    rom: &'a [u8],
+    quirks: &'a Quirks,
+    code: Vec<bool>,                        // Whether an instruction starts at each byte
+    sprite: Vec<bool>,                      // Whether each byte is drawn as a sprite
+    labels: BTreeMap<u16, LabelKind>,
+}
+
+impl<'a> Analysis<'a> {
+
+    fn new(rom: &'a [u8], quirks: &'a Quirks) -> Analysis<'a> {
+        let mut analysis = Analysis {
+            rom,
+            quirks,
+            code: vec![false; rom.len()],
+            sprite: vec![false; rom.len()],
+            labels: BTreeMap::new(),
+        };
+        analysis.follow_control_flow();
+        analysis
+    }
+
+    // Offset in the ROM of an address, if inside it
+    fn offset(&self, addr: u16) -> Option<usize> {
+        if addr < ROM_START || (addr - ROM_START) as usize >= self.rom.len() {
+            return None;
+        }
+        Some((addr - ROM_START) as usize)
+    }
+
+    fn word_at(&self, addr: u16) -> Option<u16> {
+        match self.offset(addr) {
+            Some(offset) if offset + 2 <= self.rom.len() => Some(BigEndian::read_u16(&self.rom[offset..])),
+            _ => None,
+        }
+    }
+
+    fn add_label(&mut self, addr: u16, kind: LabelKind) {
+        if self.offset(addr).is_none() {
+            return;
+        }
+        let label = self.labels.entry(addr).or_insert(kind);
+        if kind > *label {
+            *label = kind;
+        }
+    }
+
+    fn label(&self, addr: u16) -> String {
+        match self.labels.get(&addr) {
+            Some(&LabelKind::Subroutine) => format!("sub_{:03X}", addr),
+            Some(&LabelKind::Jump) => format!("label_{:03X}", addr),
+            Some(&LabelKind::Sprite) => format!("sprite_{:03X}", addr),
+            Some(&LabelKind::Data) => format!("data_{:03X}", addr),
+            None => format!("{:03X}", addr),
+        }
+    }
+
+    // Mark as code every instruction reachable from the entry point. Also keeps track of
+    // the I register along each path to find the sprites drawn
+    fn follow_control_flow(&mut self) {
+        let mut pending = vec![(ROM_START, None)];
+        while let Some((addr, mut i)) = pending.pop() {
+            let offset = match self.offset(addr) {
+                Some(offset) if !self.code[offset] => offset,
+                _ => continue,
+            };
+            let instruction = match self.word_at(addr).and_then(|word| Instruction::decode(word).ok()) {
+                Some(instruction) => instruction,
+                None => continue,
+            };
+            self.code[offset] = true;
+            let next = addr.wrapping_add(instruction.size());
+            match instruction {
+                Instruction::Jump(target) => {
+                    self.add_label(target, LabelKind::Jump);
+                    pending.push((target, i));
+                },
+                Instruction::JumpIndexed(target) => {
+                    // only the first entry of the jump table can be known
+                    self.add_label(target, LabelKind::Jump);
+                    pending.push((target, None));
+                },
+                Instruction::Call(target) => {
+                    self.add_label(target, LabelKind::Subroutine);
+                    pending.push((next, None));
+                    pending.push((target, i));
+                },
+                Instruction::Return | Instruction::Exit => {},
+                skip if skip.is_skip() => {
+                    let skipped = match self.word_at(next) {
+                        Some(0xF000) => 4,
+                        _ => 2,
+                    };
+                    pending.push((next.wrapping_add(skipped), i));
+                    pending.push((next, i));
+                },
+                other => {
+                    match other {
+                        Instruction::SetI(addr) => {
+                            self.add_label(addr, LabelKind::Data);
+                            i = Some(addr);
+                        },
+                        Instruction::SetLongI => {
+                            i = addr.checked_add(2).and_then(|addr| self.word_at(addr));
+                            if let Some(addr) = i {
+                                self.add_label(addr, LabelKind::Data);
+                            }
+                        },
+                        Instruction::Draw { n, .. } => {
+                            if let Some(addr) = i {
+                                self.mark_sprite(addr, if n == 0 { 32 } else { n as usize });
+                            }
+                        },
+                        Instruction::AddI(_) | Instruction::Font(_) | Instruction::BigFont(_) |
+                        Instruction::Store(_) | Instruction::Load(_) |
+                        Instruction::StoreRange { .. } | Instruction::LoadRange { .. } => i = None,
+                        _ => {},
+                    }
+                    pending.push((next, i));
+                },
+            }
+        }
+    }
+
+    fn mark_sprite(&mut self, addr: u16, len: usize) {
+        self.add_label(addr, LabelKind::Sprite);
+        if let Some(offset) = self.offset(addr) {
+            let end = ::std::cmp::min(offset + len, self.rom.len());
+            for byte in &mut self.sprite[offset..end] {
+                *byte = true;
+            }
+        }
+    }
+
+    // Mnemonic of the instruction at 'addr', with labels for the addresses
+    fn format_instruction_at(&self, addr: u16, instruction: Instruction) -> String {
+        match instruction {
+            Instruction::SetLongI => match addr.checked_add(2).and_then(|addr| self.word_at(addr)) {
+                Some(target) => format!("LD I, {}", self.label(target)),
+                // the operand would be past the end of the ROM
+                None => String::from("DW F000"),
+            },
+            _ => instruction.format_with(self.quirks, |target| self.label(target)),
+        }
+    }
+}
+
+// Disassemble a ROM, telling code from data by following jumps and calls from its entry point.
+// Sprite data is shown as bitmaps, and ambiguous instructions as 'quirks' have them. Bytes
+// past the end of the address space are left out
+pub fn disassemble(rom: &[u8], quirks: &Quirks) -> String {
+    let rom = &rom[..::std::cmp::min(rom.len(), MAX_ROM_SIZE)];
+    let analysis = Analysis::new(rom, quirks);
+    let mut listing = String::new();
+    let mut offset = 0;
+    while offset < rom.len() {
+        let addr = ROM_START + offset as u16;
+        if analysis.labels.contains_key(&addr) {
+            listing.push_str(&format!("{}:\n", analysis.label(addr)));
+        }
+        if analysis.code[offset] {
+            let instruction = Instruction::decode(analysis.word_at(addr).unwrap()).unwrap();
+            let len = instruction.size() as usize;
+            let bytes: Vec<String> = rom[offset..::std::cmp::min(offset + len, rom.len())].iter()
+                .map(|b| format!("{:02X}", b))
+                .collect();
+            listing.push_str(&format!("    {:03X}: {:<9} {}\n", addr, bytes.concat(), analysis.format_instruction_at(addr, instruction)));
+            offset += len;
+        } else if analysis.sprite[offset] {
+            let bitmap: String = (0..8).map(|bit| if rom[offset] & (0x80 >> bit) != 0 { '#' } else { '.' }).collect();
+            listing.push_str(&format!("    {:03X}: {:<9} DB {:02X}  {}\n", addr, format!("{:02X}", rom[offset]), rom[offset], bitmap));
+            offset += 1;
+        } else {
+            // other data, up to 8 bytes per line, until some code, sprite or label
+            let mut end = offset + 1;
+            while end < rom.len() && end - offset < 8 && !analysis.code[end] && !analysis.sprite[end] &&
+                    !analysis.labels.contains_key(&(ROM_START + end as u16)) {
+                end += 1;
+            }
+            let bytes: Vec<String> = rom[offset..end].iter().map(|b| format!("{:02X}", b)).collect();
+            listing.push_str(&format!("    {:03X}: {:<9} DB {}\n", addr, "", bytes.join(", ")));
+            offset = end;
+        }
+    }
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mnemonics() {
+        assert_eq!(mnemonic(0x00E0, &Quirks::default()), "CLS");
+        assert_eq!(mnemonic(0x2ABC, &Quirks::default()), "CALL ABC");
+        assert_eq!(mnemonic(0x8A36, &Quirks::default()), "SHR VA, V3");
+        assert_eq!(mnemonic(0xD125, &Quirks::default()), "DRW V1, V2, 5");
+        assert_eq!(mnemonic(0xF265, &Quirks::default()), "LD V2, [I]");
+        assert_eq!(mnemonic(0x5AB1, &Quirks::default()), "DW 5AB1");
+        assert_eq!(mnemonic(0xB3A0, &Quirks::default()), "JP V0, 3A0");
+        assert_eq!(mnemonic(0xB3A0, &Quirks::CHIP_48), "JP V3, 3A0");
+    }
+
+    #[test]
+    fn labels_calls_and_jumps() {
+        // 200: call 206; 202: jump 202; 204: data; 206: return
+        let listing = disassemble(&[0x22, 0x06, 0x12, 0x02, 0xAB, 0xCD, 0x00, 0xEE], &Quirks::default());
+        assert_eq!(listing, "    200: 2206      CALL sub_206\n\
+                             label_202:\n\
+                             \x20   202: 1202      JP label_202\n\
+                             \x20   204:           DB AB, CD\n\
+                             sub_206:\n\
+                             \x20   206: 00EE      RET\n");
+    }
+
+    #[test]
+    fn both_paths_of_skips_are_code() {
+        // 200: skip if V0 == 0; 202: jump 202; 204: exit
+        let listing = disassemble(&[0x30, 0x00, 0x12, 0x02, 0x00, 0xFD], &Quirks::default());
+        assert!(listing.contains("204: 00FD      EXIT"));
+    }
+
+    #[test]
+    fn sprites_as_bitmaps() {
+        // 200: I := 206; 202: draw 2 bytes; 204: exit; 206: sprite
+        let listing = disassemble(&[0xA2, 0x06, 0xD0, 0x12, 0x00, 0xFD, 0x3C, 0x81], &Quirks::default());
+        assert!(listing.contains("LD I, sprite_206\n"));
+        assert!(listing.contains("sprite_206:\n    206: 3C        DB 3C  ..####..\n    207: 81        DB 81  #......#\n"));
+    }
+
+    #[test]
+    fn long_i_operand() {
+        // 200: I := long 206; 204: exit; 206: data
+        let listing = disassemble(&[0xF0, 0x00, 0x02, 0x06, 0x00, 0xFD, 0x01], &Quirks::default());
+        assert!(listing.contains("200: F0000206  LD I, data_206\n"));
+        assert!(listing.contains("206:           DB 01\n"));
+    }
+
+    #[test]
+    fn rom_filling_the_address_space() {
+        // code all the way to FFFE, where a long I load has no room for its operand
+        let mut rom: Vec<u8> = [0x60, 0x00].iter().cloned().cycle().take(MAX_ROM_SIZE - 2).collect();
+        rom.extend_from_slice(&[0xF0, 0x00, 0xAB, 0xCD]);
+        let listing = disassemble(&rom, &Quirks::default());
+        assert!(listing.ends_with("    FFFE: F000      DW F000\n"));
+    }
+}
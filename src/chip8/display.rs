@@ -1,17 +1,29 @@
-const TOTAL_COLS: usize = 64;
-const TOTAL_ROWS: usize = 32;
-const TOTAL_PIXELS: usize = TOTAL_ROWS*TOTAL_COLS;
+const LORES_COLS: usize = 64;
+const LORES_ROWS: usize = 32;
+const HIRES_COLS: usize = 128;
+const HIRES_ROWS: usize = 64;
+const PLANES: usize = 2;
+// Bytes of the packed buffer, as returned by 'to_bytes'
+pub const PACKED_SIZE: usize = PLANES*HIRES_ROWS*HIRES_COLS/8;
 
-// A 64x32 binary pixels display for CHIP8
-#[derive(Copy)]
+// A display for CHIP8: 64x32 in low resolution mode, 128x64 in the SUPER-CHIP
+// high resolution mode. Pixels are made of two bitplanes (XO-CHIP), so each can
+// have one out of four colours. Plain CHIP-8 programs only draw on the first plane.
+// Each row of a plane is packed in a u128, column 0 being the most significant bit,
+// so a sprite row is drawn with a shift, a XOR and an AND to detect collisions
+#[derive(Copy, PartialEq, Eq, Hash)]
 pub struct Chip8Display {
-    pixels: [bool; TOTAL_PIXELS]
+    rows: [[u128; HIRES_ROWS]; PLANES],     // pixels of each plane, HIRES_COLS bits per row whatever the mode
+    hires: bool,
+    planes: u8,                             // bitmask of the planes selected for drawing
 }
 
 impl Clone for Chip8Display {
     fn clone(&self) -> Self {
         Chip8Display{
-            pixels: self.pixels,
+            rows: self.rows,
+            hires: self.hires,
+            planes: self.planes,
         }
     }
 }
@@ -19,62 +31,226 @@ impl Clone for Chip8Display {
 impl Chip8Display {
 
     pub fn new() -> Chip8Display {
-        Chip8Display{ pixels: [false; TOTAL_PIXELS] }
+        Chip8Display{ rows: [[0; HIRES_ROWS]; PLANES], hires: false, planes: 1 }
     }
 
+    // Switch resolution mode, which also clears the screen
+    pub fn set_hires(&mut self, hires: bool) {
+        self.rows = [[0; HIRES_ROWS]; PLANES];
+        self.hires = hires;
+    }
+
+    // Clear the selected planes
+    pub fn clear(&mut self) {
+        for plane in self.selected() {
+            self.rows[plane] = [0; HIRES_ROWS];
+        }
+    }
+
+    // Select the planes affected by drawing, clearing and scrolling (XO-CHIP)
+    pub fn select_planes(&mut self, planes: u8) {
+        self.planes = planes & 0x3;
+    }
+
+    // Bitmask of the planes selected for drawing
+    pub fn planes(&self) -> u8 {
+        self.planes
+    }
+
+    // Number of planes selected for drawing
+    pub fn selected_planes(&self) -> usize {
+        self.planes.count_ones() as usize
+    }
+
+    // Indexes of the selected planes
+    fn selected(&self) -> impl Iterator<Item = usize> {
+        let planes = self.planes;
+        (0..PLANES).filter(move |p| planes & (1 << p) != 0)
+    }
+
+    pub fn is_hires(&self) -> bool {
+        self.hires
+    }
+
+    // Number of columns in the current mode
+    pub fn cols(&self) -> usize {
+        if self.hires { HIRES_COLS } else { LORES_COLS }
+    }
+
+    // Number of rows in the current mode
+    pub fn rows(&self) -> usize {
+        if self.hires { HIRES_ROWS } else { LORES_ROWS }
+    }
+
+    // Bits of a row for the columns of the current mode
+    fn visible_cols(&self) -> u128 {
+        if self.hires { !0 } else { !0 << (HIRES_COLS - LORES_COLS) }
+    }
+
+    // Whether the pixel is lit in any plane
     pub fn get_pixel(&self, col: u8, row: u8) -> bool {
-        self.pixels[pixel_index(col, row)]
+        self.get_color(col, row) != 0
+    }
+
+    // Colour of the pixel: bit 0 is set if lit in the first plane, bit 1 if lit in the second
+    pub fn get_color(&self, col: u8, row: u8) -> u8 {
+        let (c, r) = (col as usize, row as usize);
+        // Don't care about managing out of bounds errors here, just check as preconditions
+        if r >= self.rows() { panic!("Row index out of bounds: {}", r) }
+        if c >= self.cols() { panic!("Column index out of bounds: {}", c) }
+        let bit = 1 << (HIRES_COLS - 1 - c);
+        (0..PLANES).fold(0, |color, p| color | ((self.rows[p][r] & bit != 0) as u8) << p)
     }
 
-    fn flip_pixel(&mut self, col: u8, row: u8) -> bool {
-        if col as usize >= TOTAL_COLS || row as usize >= TOTAL_ROWS {
+    // A row of a plane (0 or 1), column 0 being the most significant bit. In low
+    // resolution mode only the 64 most significant bits are used
+    pub fn row_bits(&self, plane: usize, row: usize) -> u128 {
+        self.rows[plane][row]
+    }
+
+    // All the rows of a plane (0 or 1) in the current mode
+    pub fn plane_rows(&self, plane: usize) -> &[u128] {
+        &self.rows[plane][..self.rows()]
+    }
+
+    // The whole buffer as bytes, for snapshots: the rows of each plane, most significant byte first
+    pub fn to_bytes(self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(PACKED_SIZE);
+        for row in self.rows.iter().flat_map(|plane| plane.iter()) {
+            bytes.extend((0..16).rev().map(|i| (row >> (i * 8)) as u8));
+        }
+        bytes
+    }
+
+    // Rebuild a display from the values of 'to_bytes', 'hires' and the selected planes,
+    // if they are valid
+    pub fn from_bytes(bytes: &[u8], hires: bool, planes: u8) -> Option<Chip8Display> {
+        if bytes.len() != PACKED_SIZE || planes > 3 {
+            return None;
+        }
+        let mut display = Chip8Display::new();
+        display.hires = hires;
+        display.planes = planes;
+        for (i, chunk) in bytes.chunks(16).enumerate() {
+            let row = chunk.iter().fold(0u128, |row, &b| row << 8 | b as u128);
+            // nothing can be drawn out of the current mode's columns
+            if row & !display.visible_cols() != 0 || (i % HIRES_ROWS >= display.rows() && row != 0) {
+                return None;
+            }
+            display.rows[i / HIRES_ROWS][i % HIRES_ROWS] = row;
+        }
+        Some(display)
+    }
+
+    // XOR a row of pixels, given as the most significant bits of 'bits', starting at
+    // 'col' in the given plane. Pixels past the right edge are dropped, or drawn from
+    // the left edge on if wrapping. Return whether any lit pixel was turned off
+    fn draw_row(&mut self, col: usize, row: usize, bits: u128, plane: u8, wrap: bool) -> bool {
+        if col >= self.cols() || row >= self.rows() {
             return false;
         }
-        let idx = pixel_index(col, row);
-        let prev = self.pixels[idx];
-        self.pixels[idx] = !prev;
-        prev
+        let mut shifted = bits >> col;
+        if wrap && col > 0 {
+            shifted |= bits << (self.cols() - col);
+        }
+        let shifted = shifted & self.visible_cols();
+        let target = &mut self.rows[(plane >> 1) as usize][row];
+        let collision = *target & shifted != 0;
+        *target ^= shifted;
+        collision
+    }
+
+    // Where a sprite starts: coordinates past the edges wrap around, whatever the mode
+    fn sprite_origin(&self, col: u8, row: u8) -> (usize, usize) {
+        (col as usize % self.cols(), row as usize % self.rows())
     }
 
-    // Draw a byte (as part of a sprite)
-    fn draw_byte(&mut self, col: u8, row: u8, byte: u8) -> bool {
+    // Screen row for the row 'index' of a sprite starting at 'row', if it isn't clipped
+    fn sprite_row(&self, row: usize, index: usize, wrap: bool) -> Option<usize> {
+        match row + index {
+            r if r < self.rows() => Some(r),
+            r if wrap => Some(r % self.rows()),
+            _ => None,
+        }
+    }
+
+    // Draw a sprite 8 pixels wide. 'bytes' holds the sprite for each of the
+    // selected planes, one after the other. The parts past the edges are clipped,
+    // or drawn on the opposite edge if 'wrap' is set
+    pub fn draw_sprite(&mut self, col: u8, row: u8, bytes: &[u8], wrap: bool) -> bool {
+        let (col, row) = self.sprite_origin(col, row);
         let mut collision = false;
-        let bits: [bool; 8] = [
-            (byte & 0x80) != 0,
-            (byte & 0x40) != 0,
-            (byte & 0x20) != 0,
-            (byte & 0x10) != 0,
-            (byte & 0x08) != 0,
-            (byte & 0x04) != 0,
-            (byte & 0x02) != 0,
-            (byte & 0x01) != 0,
-        ];
-
-        for (i, b) in bits.iter().enumerate() {
-            if *b {
-                let col_idx = col + i as u8;
-                collision |= self.flip_pixel(col_idx, row);
+        for (plane, plane_bytes) in self.plane_chunks(bytes) {
+            for (index, byte) in plane_bytes.iter().enumerate() {
+                if let Some(r) = self.sprite_row(row, index, wrap) {
+                    let bits = (*byte as u128) << (HIRES_COLS - 8);
+                    collision |= self.draw_row(col, r, bits, plane, wrap);
+                }
             }
         }
         collision
     }
 
-    pub fn draw_sprite(&mut self, col: u8, row: u8, bytes: &[u8]) -> bool {
+    // Draw a 16x16 SUPER-CHIP sprite, made of 16 rows of 2 bytes for each of the
+    // selected planes. Edges are handled as in 'draw_sprite'
+    pub fn draw_wide_sprite(&mut self, col: u8, row: u8, bytes: &[u8], wrap: bool) -> bool {
+        let (col, row) = self.sprite_origin(col, row);
         let mut collision = false;
-        for (index, byte) in bytes.iter().enumerate() {
-            collision |= self.draw_byte(col, row + (index as u8), *byte);
+        for (plane, plane_bytes) in self.plane_chunks(bytes) {
+            for (index, pair) in plane_bytes.chunks(2).enumerate() {
+                if let Some(r) = self.sprite_row(row, index, wrap) {
+                    let bits = (pair[0] as u128) << (HIRES_COLS - 8) | (pair[1] as u128) << (HIRES_COLS - 16);
+                    collision |= self.draw_row(col, r, bits, plane, wrap);
+                }
+            }
         }
         collision
     }
-}
 
-fn pixel_index(col: u8, row: u8) -> usize {
-    let r = row as usize;
-    let c = col as usize;
-    // Don't care about managing out of bounds errors here, just check as preconditions
-    if r >= TOTAL_ROWS { panic!("Row index out of bounds: {}", r) }
-    if c >= TOTAL_COLS { panic!("Column index out of bounds: {}", c) }
-    TOTAL_COLS*r + c
+    // Split sprite data among the selected planes, given as their bit in the plane mask
+    fn plane_chunks<'a>(&self, bytes: &'a [u8]) -> impl Iterator<Item = (u8, &'a [u8])> + 'a {
+        let len = bytes.len() / ::std::cmp::max(self.selected_planes(), 1);
+        self.selected().map(|p| 1 << p).zip(bytes.chunks(::std::cmp::max(len, 1)))
+    }
+
+    // Scroll the screen 'n' pixels down (in the pixels of the current mode)
+    pub fn scroll_down(&mut self, n: usize) {
+        self.scroll(0, n as isize);
+    }
+
+    // Scroll the screen 'n' pixels up (in the pixels of the current mode)
+    pub fn scroll_up(&mut self, n: usize) {
+        self.scroll(0, -(n as isize));
+    }
+
+    // Scroll the screen 'n' pixels right (in the pixels of the current mode)
+    pub fn scroll_right(&mut self, n: usize) {
+        self.scroll(n as isize, 0);
+    }
+
+    // Scroll the screen 'n' pixels left (in the pixels of the current mode)
+    pub fn scroll_left(&mut self, n: usize) {
+        self.scroll(-(n as isize), 0);
+    }
+
+    // Move the selected planes by the given offsets, filling with unlit pixels
+    fn scroll(&mut self, d_col: isize, d_row: isize) {
+        let rows = self.rows() as isize;
+        let visible = self.visible_cols();
+        for plane in self.selected() {
+            let prev = self.rows[plane];
+            for r in 0..rows {
+                let src_r = r - d_row;
+                let moved = if src_r >= 0 && src_r < rows { prev[src_r as usize] } else { 0 };
+                let moved = match d_col {
+                    d if d.abs() >= HIRES_COLS as isize => 0,
+                    d if d >= 0 => moved >> d,
+                    d => moved << -d,
+                };
+                self.rows[plane][r as usize] = moved & visible;
+            }
+        }
+    }
 }
 
 #[cfg(test)]
@@ -85,7 +261,7 @@ mod tests {
     fn flip_unset_pixel() {
         let mut d = Chip8Display::new();
         assert_eq!(d.get_pixel(0,0), false);
-        let collision = d.flip_pixel(0,0);
+        let collision = d.draw_sprite(0, 0, &[0x80], false);
         assert_eq!(collision, false);
         assert_eq!(d.get_pixel(0,0), true);
     }
@@ -94,18 +270,18 @@ mod tests {
     fn flip_set_pixel() {
         let mut d = Chip8Display::new();
         assert_eq!(d.get_pixel(0,0), false);
-        let mut collision = d.flip_pixel(0,0);
+        let mut collision = d.draw_sprite(0, 0, &[0x80], false);
         assert_eq!(collision, false);
         assert_eq!(d.get_pixel(0,0), true);
-        collision = d.flip_pixel(0,0);
+        collision = d.draw_sprite(0, 0, &[0x80], false);
         assert_eq!(collision, true);
         assert_eq!(d.get_pixel(0,0), false);
     }
 
     #[test]
-    fn draw_byte_no_collision() {
+    fn draw_one_row_no_collision() {
         let mut d = Chip8Display::new();
-        let collision = d.draw_byte(0, 0, 0xAA);
+        let collision = d.draw_sprite(0, 0, &[0xAA], false);
         assert_eq!(collision, false);
         for c in 0..64 {
             for r in 0..32 {
@@ -119,10 +295,10 @@ mod tests {
     }
 
     #[test]
-    fn draw_byte_with_collision() {
+    fn draw_one_row_with_collision() {
         let mut d = Chip8Display::new();
-        d.flip_pixel(0,0);
-        let collision = d.draw_byte(0, 0, 0xAA);
+        d.draw_sprite(0, 0, &[0x80], false);
+        let collision = d.draw_sprite(0, 0, &[0xAA], false);
         assert_eq!(collision, true);
         for c in 0..64 {
             for r in 0..32 {
@@ -147,16 +323,210 @@ mod tests {
     #[test]
     fn draw_sprite_no_collision() {
         let mut d = Chip8Display::new();
-        let collision = d.draw_sprite(0, 0, ALIEN_SPRITE);
+        let collision = d.draw_sprite(0, 0, ALIEN_SPRITE, false);
         assert_eq!(collision, false);
     }
 
     #[test]
     fn draw_sprite_with_collision() {
         let mut d = Chip8Display::new();
-        let mut collision = d.draw_sprite(0, 0, ALIEN_SPRITE);
+        let mut collision = d.draw_sprite(0, 0, ALIEN_SPRITE, false);
         assert_eq!(collision, false);
-        collision = d.draw_sprite(1, 1, ALIEN_SPRITE);
+        collision = d.draw_sprite(1, 1, ALIEN_SPRITE, false);
         assert_eq!(collision, true);
     }
+
+    #[test]
+    fn switch_to_hires() {
+        let mut d = Chip8Display::new();
+        d.draw_sprite(0, 0, &[0x80], false);
+        d.set_hires(true);
+        assert_eq!((d.cols(), d.rows()), (128, 64));
+        assert!(!d.get_pixel(0,0));
+        assert!(!d.draw_sprite(127, 63, &[0x80], false));
+        assert!(d.get_pixel(127,63));
+        d.set_hires(false);
+        assert_eq!((d.cols(), d.rows()), (64, 32));
+        assert!(!d.get_pixel(63,31));
+    }
+
+    #[test]
+    fn draw_wide_sprite() {
+        let mut d = Chip8Display::new();
+        d.set_hires(true);
+        let sprite = [0xFFu8; 32];
+        let mut collision = d.draw_wide_sprite(100, 40, &sprite, false);
+        assert!(!collision);
+        for c in 0..128 {
+            for r in 0..64 {
+                let inside = (100..116).contains(&c) && (40..56).contains(&r);
+                assert_eq!(d.get_pixel(c, r), inside);
+            }
+        }
+        collision = d.draw_wide_sprite(115, 55, &sprite, false);
+        assert!(collision);
+    }
+
+    #[test]
+    fn draw_sprite_on_both_planes() {
+        let mut d = Chip8Display::new();
+        d.select_planes(3);
+        assert_eq!(d.selected_planes(), 2);
+        let collision = d.draw_sprite(0, 0, &[0xC0, 0xA0], false);
+        assert!(!collision);
+        assert_eq!(d.get_color(0, 0), 3);
+        assert_eq!(d.get_color(1, 0), 1);
+        assert_eq!(d.get_color(2, 0), 2);
+        assert_eq!(d.get_color(3, 0), 0);
+        d.select_planes(2);
+        assert!(d.draw_sprite(0, 0, &[0x20], false));
+        assert_eq!(d.get_color(2, 0), 0);
+        assert!(!d.get_pixel(2, 0));
+    }
+
+    #[test]
+    fn clear_and_scroll_selected_planes() {
+        let mut d = Chip8Display::new();
+        d.select_planes(3);
+        d.draw_sprite(0, 1, &[0x80, 0x80], false);
+        d.select_planes(2);
+        d.scroll_up(1);
+        assert_eq!(d.get_color(0, 0), 2);
+        assert_eq!(d.get_color(0, 1), 1);
+        d.clear();
+        assert_eq!(d.get_color(0, 0), 0);
+        assert_eq!(d.get_color(0, 1), 1);
+        d.select_planes(0);
+        assert!(!d.draw_sprite(0, 1, &[], false));
+        assert_eq!(d.get_color(0, 1), 1);
+    }
+
+    // Lit pixels of the screen
+    fn lit(d: &Chip8Display) -> Vec<(u8, u8)> {
+        let mut pixels = Vec::new();
+        for r in 0..d.rows() as u8 {
+            for c in 0..d.cols() as u8 {
+                if d.get_pixel(c, r) {
+                    pixels.push((c, r));
+                }
+            }
+        }
+        pixels
+    }
+
+    #[test]
+    fn start_coordinates_wrap() {
+        for &wrap in &[false, true] {
+            let mut d = Chip8Display::new();
+            // 70 % 64, 255 % 32
+            d.draw_sprite(70, 255, &[0x80], wrap);
+            assert_eq!(lit(&d), vec![(6, 31)]);
+            d.set_hires(true);
+            d.clear();
+            // 200 % 128, 100 % 64
+            d.draw_wide_sprite(200, 100, &[0x80; 32], wrap);
+            assert_eq!(&lit(&d)[..2], &[(72, 36), (80, 36)]);
+        }
+    }
+
+    #[test]
+    fn right_edge() {
+        let mut d = Chip8Display::new();
+        d.draw_sprite(60, 0, &[0xFF], false);
+        assert_eq!(lit(&d), vec![(60, 0), (61, 0), (62, 0), (63, 0)]);
+        let mut d = Chip8Display::new();
+        d.draw_sprite(60, 0, &[0xFF], true);
+        assert_eq!(lit(&d), vec![(0, 0), (1, 0), (2, 0), (3, 0), (60, 0), (61, 0), (62, 0), (63, 0)]);
+    }
+
+    #[test]
+    fn left_and_top_edges() {
+        for &wrap in &[false, true] {
+            let mut d = Chip8Display::new();
+            d.draw_sprite(0, 0, &[0x81, 0x00, 0x81], wrap);
+            assert_eq!(lit(&d), vec![(0, 0), (7, 0), (0, 2), (7, 2)]);
+        }
+    }
+
+    #[test]
+    fn bottom_edge() {
+        let mut d = Chip8Display::new();
+        d.draw_sprite(3, 30, &[0x80, 0x80, 0x80, 0x80], false);
+        assert_eq!(lit(&d), vec![(3, 30), (3, 31)]);
+        let mut d = Chip8Display::new();
+        d.draw_sprite(3, 30, &[0x80, 0x80, 0x80, 0x80], true);
+        assert_eq!(lit(&d), vec![(3, 0), (3, 1), (3, 30), (3, 31)]);
+    }
+
+    #[test]
+    fn bottom_right_corner() {
+        let mut d = Chip8Display::new();
+        d.draw_sprite(63, 31, &[0xC0, 0xC0], false);
+        assert_eq!(lit(&d), vec![(63, 31)]);
+        let mut d = Chip8Display::new();
+        d.draw_sprite(63, 31, &[0xC0, 0xC0], true);
+        assert_eq!(lit(&d), vec![(0, 0), (63, 0), (0, 31), (63, 31)]);
+        // and collisions are detected on the wrapped part
+        assert!(d.draw_sprite(0, 0, &[0x80], true));
+    }
+
+    #[test]
+    fn hires_corners() {
+        let mut d = Chip8Display::new();
+        d.set_hires(true);
+        d.draw_wide_sprite(127, 63, &[0xC0, 0x01, 0xC0, 0x01], false);
+        assert_eq!(lit(&d), vec![(127, 63)]);
+        d.clear();
+        d.draw_wide_sprite(127, 63, &[0xC0, 0x01, 0xC0, 0x01], true);
+        assert_eq!(lit(&d), vec![(0, 0), (14, 0), (127, 0), (0, 63), (14, 63), (127, 63)]);
+    }
+
+    #[test]
+    fn packed_rows() {
+        let mut d = Chip8Display::new();
+        d.draw_sprite(62, 1, &[0xFF], false);
+        // clipped at the right edge of the low resolution screen
+        assert_eq!(d.row_bits(0, 1), 0b11 << 64);
+        assert_eq!(d.plane_rows(0).len(), 32);
+        assert_eq!(d.plane_rows(1).iter().filter(|&&r| r != 0).count(), 0);
+        let restored = Chip8Display::from_bytes(&d.to_bytes(), false, 1).unwrap();
+        assert!(restored == d);
+        // pixels out of the low resolution screen can't be restored
+        let mut bytes = d.to_bytes();
+        bytes[15] = 1;
+        assert!(Chip8Display::from_bytes(&bytes, false, 1).is_none());
+        assert!(Chip8Display::from_bytes(&bytes, true, 1).is_some());
+    }
+
+    #[test]
+    fn scroll_down() {
+        let mut d = Chip8Display::new();
+        d.draw_sprite(3, 0, &[0x80], false);
+        d.draw_sprite(3, 30, &[0x80], false);
+        d.scroll_down(1);
+        assert!(!d.get_pixel(3,0));
+        assert!(d.get_pixel(3,1));
+        assert!(d.get_pixel(3,31));
+        d.scroll_down(2);
+        assert!(d.get_pixel(3,3));
+        assert!(!d.get_pixel(3,31));
+    }
+
+    #[test]
+    fn scroll_sideways() {
+        let mut d = Chip8Display::new();
+        d.set_hires(true);
+        d.draw_sprite(0, 5, &[0x80], false);
+        d.draw_sprite(125, 5, &[0x80], false);
+        d.scroll_right(4);
+        assert!(!d.get_pixel(0,5));
+        assert!(d.get_pixel(4,5));
+        assert!(!d.get_pixel(125,5));
+        d.scroll_left(4);
+        d.scroll_left(4);
+        assert!(!d.get_pixel(0,5));
+        for c in 0..128 {
+            assert!(!d.get_pixel(c,5));
+        }
+    }
 }
@@ -0,0 +1,46 @@
+use std::error::Error;
+use std::fmt;
+
+// Errors that can stop the execution of a CHIP-8 program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Chip8Error {
+    // The word at 'addr' doesn't decode to any known instruction
+    UnknownOpcode { opcode: u16, addr: u16 },
+    // 00EE executed at 'addr' with an empty call stack
+    StackUnderflow { addr: u16 },
+    // 2nnn executed at 'addr' with no room left in the call stack
+    StackOverflow { addr: u16 },
+    // The PC doesn't point to a full instruction inside RAM
+    PcOutOfRange { pc: u16 },
+    // The instruction at 'pc' tried to access the RAM address 'addr', which doesn't exist
+    MemoryOutOfRange { pc: u16, addr: usize },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Chip8Error::UnknownOpcode { opcode, addr } =>
+                write!(f, "unknown opcode {:04X} at {:03X}", opcode, addr),
+            Chip8Error::StackUnderflow { addr } =>
+                write!(f, "return with empty stack at {:03X}", addr),
+            Chip8Error::StackOverflow { addr } =>
+                write!(f, "stack overflow calling subroutine at {:03X}", addr),
+            Chip8Error::PcOutOfRange { pc } =>
+                write!(f, "program counter out of memory: {:04X}", pc),
+            Chip8Error::MemoryOutOfRange { pc, addr } =>
+                write!(f, "memory access out of range at {:03X}: address {:04X}", pc, addr),
+        }
+    }
+}
+
+impl Error for Chip8Error {
+    fn description(&self) -> &str {
+        match *self {
+            Chip8Error::UnknownOpcode { .. } => "unknown opcode",
+            Chip8Error::StackUnderflow { .. } => "stack underflow",
+            Chip8Error::StackOverflow { .. } => "stack overflow",
+            Chip8Error::PcOutOfRange { .. } => "program counter out of range",
+            Chip8Error::MemoryOutOfRange { .. } => "memory access out of range",
+        }
+    }
+}
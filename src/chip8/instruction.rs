@@ -0,0 +1,388 @@
+use std::error::Error;
+use std::fmt;
+use super::Quirks;
+
+// Decoded CHIP-8 instruction, shared by the interpreter and the disassembler
+// so both agree on the encodings (including SUPER-CHIP and XO-CHIP ones)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    // 00E0: Clear screen
+    ClearScreen,
+    // 00EE: Return from subroutine
+    Return,
+    // 00Cn: Scroll display n pixels down (SUPER-CHIP)
+    ScrollDown(u8),
+    // 00Dn: Scroll display n pixels up (XO-CHIP)
+    ScrollUp(u8),
+    // 00FB: Scroll display 4 pixels right (SUPER-CHIP)
+    ScrollRight,
+    // 00FC: Scroll display 4 pixels left (SUPER-CHIP)
+    ScrollLeft,
+    // 00FD: Exit interpreter (SUPER-CHIP)
+    Exit,
+    // 00FE: Disable high resolution mode (SUPER-CHIP)
+    LowRes,
+    // 00FF: Enable high resolution mode (SUPER-CHIP)
+    HighRes,
+    // 1nnn: Jump to 'nnn' address
+    Jump(u16),
+    // 2nnn: Call subroutine at 'nnn'
+    Call(u16),
+    // 3xkk: Skip next instruction if Vx == kk
+    SkipEqImm { x: u8, kk: u8 },
+    // 4xkk: Skip next instruction if Vx != kk
+    SkipNeImm { x: u8, kk: u8 },
+    // 5xy0: Skip next instruction if Vx = Vy
+    SkipEq { x: u8, y: u8 },
+    // 5xy2: Store registers Vx through Vy in memory starting at location I (XO-CHIP)
+    StoreRange { x: u8, y: u8 },
+    // 5xy3: Read registers Vx through Vy from memory starting at location I (XO-CHIP)
+    LoadRange { x: u8, y: u8 },
+    // 6xkk: Put the value kk into register Vx
+    LoadImm { x: u8, kk: u8 },
+    // 7xkk: Add the value kk to register Vx
+    AddImm { x: u8, kk: u8 },
+    // 8xy0: Store the value of register Vy in register Vx
+    Move { x: u8, y: u8 },
+    // 8xy1: Set Vx = Vx OR Vy
+    Or { x: u8, y: u8 },
+    // 8xy2: Set Vx = Vx AND Vy
+    And { x: u8, y: u8 },
+    // 8xy3: Set Vx = Vx XOR Vy
+    Xor { x: u8, y: u8 },
+    // 8xy4: Set Vx = Vx + Vy, set VF = carry
+    Add { x: u8, y: u8 },
+    // 8xy5: Set Vx = Vx - Vy, set VF = (Vx>Vy)
+    Sub { x: u8, y: u8 },
+    // 8xy6: Set Vx = Vx SHR 1 (or Vx = Vy SHR 1)
+    Shr { x: u8, y: u8 },
+    // 8xy7: Set Vx = Vy - Vx, set VF = (Vy>Vx)
+    SubN { x: u8, y: u8 },
+    // 8xyE: Set Vx = Vx SHL 1 (or Vx = Vy SHL 1)
+    Shl { x: u8, y: u8 },
+    // 9xy0: Skip next instruction if Vx != Vy
+    SkipNe { x: u8, y: u8 },
+    // Annn: Set I = nnn
+    SetI(u16),
+    // Bnnn: Jump to location nnn + V0 (or xnn + Vx)
+    JumpIndexed(u16),
+    // Cxkk: Set Vx = random byte AND kk
+    Random { x: u8, kk: u8 },
+    // Dxyn: Display n-byte sprite starting at memory location I at (Vx, Vy),
+    // or a 16x16 sprite if n is 0 (SUPER-CHIP)
+    Draw { x: u8, y: u8, n: u8 },
+    // Ex9E: Skip next instruction if key with the value of Vx is pressed
+    SkipKeyDown(u8),
+    // ExA1: Skip next instruction if key with the value of Vx is NOT pressed
+    SkipKeyUp(u8),
+    // F000 NNNN: Set I = NNNN, read from the next word (XO-CHIP)
+    SetLongI,
+    // Fn01: Select drawing planes n (XO-CHIP)
+    SelectPlanes(u8),
+    // F002: Load 16 bytes starting at I into the audio pattern buffer (XO-CHIP)
+    LoadAudio,
+    // Fx07: Set Vx = delay timer value
+    GetDelay(u8),
+    // Fx0A: Wait for a key press, store the value of the key in Vx
+    WaitKey(u8),
+    // Fx15: Set delay timer = Vx
+    SetDelay(u8),
+    // Fx18: Set sound timer = Vx
+    SetSound(u8),
+    // Fx1E: Set I = I + Vx
+    AddI(u8),
+    // Fx29: Set I = location of sprite for digit Vx
+    Font(u8),
+    // Fx30: Set I = location of big sprite for digit Vx (SUPER-CHIP)
+    BigFont(u8),
+    // Fx33: Store BCD representation of Vx in memory locations I, I+1, and I+2
+    Bcd(u8),
+    // Fx3A: Set audio pattern pitch = Vx (XO-CHIP)
+    Pitch(u8),
+    // Fx55: Store registers V0 through Vx in memory starting at location I
+    Store(u8),
+    // Fx65: Read registers V0 through Vx from memory starting at location I
+    Load(u8),
+    // Fx75: Store registers V0 through Vx in RPL user flags (SUPER-CHIP)
+    SaveFlags(u8),
+    // Fx85: Read registers V0 through Vx from RPL user flags (SUPER-CHIP)
+    LoadFlags(u8),
+}
+
+use self::Instruction::*;
+use super::Chip8Instruction;
+
+// A word that isn't any known instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DecodeError {
+    pub word: u16,
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "unknown instruction {:04X}", self.word)
+    }
+}
+
+impl Error for DecodeError {
+    fn description(&self) -> &str {
+        "unknown instruction"
+    }
+}
+
+impl Instruction {
+
+    // Decode an instruction word, if it is a known instruction in any of the supported variants
+    pub fn decode(word: u16) -> Result<Instruction, DecodeError> {
+        let op = Chip8Instruction(word);
+        let nibbles = [op.nibble(1), op.nibble(2), op.nibble(3), op.nibble(4)];
+        let nnn = word & 0xFFF;
+        let kk = word as u8;
+        let instruction = match &nibbles {
+            &[0,0,0xE,0] => ClearScreen,
+            &[0,0,0xE,0xE] => Return,
+            &[0,0,0xC,n] => ScrollDown(n),
+            &[0,0,0xD,n] => ScrollUp(n),
+            &[0,0,0xF,0xB] => ScrollRight,
+            &[0,0,0xF,0xC] => ScrollLeft,
+            &[0,0,0xF,0xD] => Exit,
+            &[0,0,0xF,0xE] => LowRes,
+            &[0,0,0xF,0xF] => HighRes,
+            &[1,_,_,_] => Jump(nnn),
+            &[2,_,_,_] => Call(nnn),
+            &[3,x,_,_] => SkipEqImm { x, kk },
+            &[4,x,_,_] => SkipNeImm { x, kk },
+            &[5,x,y,0] => SkipEq { x, y },
+            &[5,x,y,2] => StoreRange { x, y },
+            &[5,x,y,3] => LoadRange { x, y },
+            &[6,x,_,_] => LoadImm { x, kk },
+            &[7,x,_,_] => AddImm { x, kk },
+            &[8,x,y,0] => Move { x, y },
+            &[8,x,y,1] => Or { x, y },
+            &[8,x,y,2] => And { x, y },
+            &[8,x,y,3] => Xor { x, y },
+            &[8,x,y,4] => Add { x, y },
+            &[8,x,y,5] => Sub { x, y },
+            &[8,x,y,6] => Shr { x, y },
+            &[8,x,y,7] => SubN { x, y },
+            &[8,x,y,0xE] => Shl { x, y },
+            &[9,x,y,0] => SkipNe { x, y },
+            &[0xA,_,_,_] => SetI(nnn),
+            &[0xB,_,_,_] => JumpIndexed(nnn),
+            &[0xC,x,_,_] => Random { x, kk },
+            &[0xD,x,y,n] => Draw { x, y, n },
+            &[0xE,x,9,0xE] => SkipKeyDown(x),
+            &[0xE,x,0xA,1] => SkipKeyUp(x),
+            &[0xF,0,0,0] => SetLongI,
+            &[0xF,n,0,1] => SelectPlanes(n),
+            &[0xF,0,0,2] => LoadAudio,
+            &[0xF,x,0,7] => GetDelay(x),
+            &[0xF,x,0,0xA] => WaitKey(x),
+            &[0xF,x,1,5] => SetDelay(x),
+            &[0xF,x,1,8] => SetSound(x),
+            &[0xF,x,1,0xE] => AddI(x),
+            &[0xF,x,2,9] => Font(x),
+            &[0xF,x,3,0] => BigFont(x),
+            &[0xF,x,3,3] => Bcd(x),
+            &[0xF,x,3,0xA] => Pitch(x),
+            &[0xF,x,5,5] => Store(x),
+            &[0xF,x,6,5] => Load(x),
+            &[0xF,x,7,5] => SaveFlags(x),
+            &[0xF,x,8,5] => LoadFlags(x),
+            _ => return Err(DecodeError { word }),
+        };
+        Ok(instruction)
+    }
+
+    // Instruction word, the inverse of 'decode' (F000 NNNN is just F000, without its operand)
+    pub fn encode(&self) -> u16 {
+        let xy = |op: u16, x: u8, y: u8, n: u16| op << 12 | (x as u16) << 8 | (y as u16) << 4 | n;
+        let xkk = |op: u16, x: u8, kk: u8| op << 12 | (x as u16) << 8 | kk as u16;
+        let fx = |x: u8, low: u16| 0xF000 | (x as u16) << 8 | low;
+        match *self {
+            ClearScreen => 0x00E0,
+            Return => 0x00EE,
+            ScrollDown(n) => 0x00C0 | n as u16,
+            ScrollUp(n) => 0x00D0 | n as u16,
+            ScrollRight => 0x00FB,
+            ScrollLeft => 0x00FC,
+            Exit => 0x00FD,
+            LowRes => 0x00FE,
+            HighRes => 0x00FF,
+            Jump(nnn) => 0x1000 | nnn,
+            Call(nnn) => 0x2000 | nnn,
+            SkipEqImm { x, kk } => xkk(3, x, kk),
+            SkipNeImm { x, kk } => xkk(4, x, kk),
+            SkipEq { x, y } => xy(5, x, y, 0),
+            StoreRange { x, y } => xy(5, x, y, 2),
+            LoadRange { x, y } => xy(5, x, y, 3),
+            LoadImm { x, kk } => xkk(6, x, kk),
+            AddImm { x, kk } => xkk(7, x, kk),
+            Move { x, y } => xy(8, x, y, 0),
+            Or { x, y } => xy(8, x, y, 1),
+            And { x, y } => xy(8, x, y, 2),
+            Xor { x, y } => xy(8, x, y, 3),
+            Add { x, y } => xy(8, x, y, 4),
+            Sub { x, y } => xy(8, x, y, 5),
+            Shr { x, y } => xy(8, x, y, 6),
+            SubN { x, y } => xy(8, x, y, 7),
+            Shl { x, y } => xy(8, x, y, 0xE),
+            SkipNe { x, y } => xy(9, x, y, 0),
+            SetI(nnn) => 0xA000 | nnn,
+            JumpIndexed(nnn) => 0xB000 | nnn,
+            Random { x, kk } => xkk(0xC, x, kk),
+            Draw { x, y, n } => xy(0xD, x, y, n as u16),
+            SkipKeyDown(x) => xkk(0xE, x, 0x9E),
+            SkipKeyUp(x) => xkk(0xE, x, 0xA1),
+            SetLongI => 0xF000,
+            SelectPlanes(n) => fx(n, 0x01),
+            LoadAudio => 0xF002,
+            GetDelay(x) => fx(x, 0x07),
+            WaitKey(x) => fx(x, 0x0A),
+            SetDelay(x) => fx(x, 0x15),
+            SetSound(x) => fx(x, 0x18),
+            AddI(x) => fx(x, 0x1E),
+            Font(x) => fx(x, 0x29),
+            BigFont(x) => fx(x, 0x30),
+            Bcd(x) => fx(x, 0x33),
+            Pitch(x) => fx(x, 0x3A),
+            Store(x) => fx(x, 0x55),
+            Load(x) => fx(x, 0x65),
+            SaveFlags(x) => fx(x, 0x75),
+            LoadFlags(x) => fx(x, 0x85),
+        }
+    }
+
+    // Mnemonic in the style of Cowgod's reference, writing addresses with 'addr'. Bnnn
+    // names the register it adds to the address as 'quirks' have it
+    pub fn format_with<F: Fn(u16) -> String>(&self, quirks: &Quirks, addr: F) -> String {
+        match *self {
+            ClearScreen => String::from("CLS"),
+            Return => String::from("RET"),
+            ScrollDown(n) => format!("SCD {}", n),
+            ScrollUp(n) => format!("SCU {}", n),
+            ScrollRight => String::from("SCR"),
+            ScrollLeft => String::from("SCL"),
+            Exit => String::from("EXIT"),
+            LowRes => String::from("LOW"),
+            HighRes => String::from("HIGH"),
+            Jump(nnn) => format!("JP {}", addr(nnn)),
+            Call(nnn) => format!("CALL {}", addr(nnn)),
+            SkipEqImm { x, kk } => format!("SE V{:X}, {:02X}", x, kk),
+            SkipNeImm { x, kk } => format!("SNE V{:X}, {:02X}", x, kk),
+            SkipEq { x, y } => format!("SE V{:X}, V{:X}", x, y),
+            StoreRange { x, y } => format!("SAVE V{:X}-V{:X}", x, y),
+            LoadRange { x, y } => format!("LOAD V{:X}-V{:X}", x, y),
+            LoadImm { x, kk } => format!("LD V{:X}, {:02X}", x, kk),
+            AddImm { x, kk } => format!("ADD V{:X}, {:02X}", x, kk),
+            Move { x, y } => format!("LD V{:X}, V{:X}", x, y),
+            Or { x, y } => format!("OR V{:X}, V{:X}", x, y),
+            And { x, y } => format!("AND V{:X}, V{:X}", x, y),
+            Xor { x, y } => format!("XOR V{:X}, V{:X}", x, y),
+            Add { x, y } => format!("ADD V{:X}, V{:X}", x, y),
+            Sub { x, y } => format!("SUB V{:X}, V{:X}", x, y),
+            Shr { x, y } => format!("SHR V{:X}, V{:X}", x, y),
+            SubN { x, y } => format!("SUBN V{:X}, V{:X}", x, y),
+            Shl { x, y } => format!("SHL V{:X}, V{:X}", x, y),
+            SkipNe { x, y } => format!("SNE V{:X}, V{:X}", x, y),
+            SetI(nnn) => format!("LD I, {}", addr(nnn)),
+            JumpIndexed(nnn) => {
+                let x = if quirks.jump_uses_vx { nnn >> 8 } else { 0 };
+                format!("JP V{:X}, {}", x, addr(nnn))
+            },
+            Random { x, kk } => format!("RND V{:X}, {:02X}", x, kk),
+            Draw { x, y, n } => format!("DRW V{:X}, V{:X}, {:X}", x, y, n),
+            SkipKeyDown(x) => format!("SKP V{:X}", x),
+            SkipKeyUp(x) => format!("SKNP V{:X}", x),
+            SetLongI => String::from("LD I, LONG"),
+            SelectPlanes(n) => format!("PLANE {}", n),
+            LoadAudio => String::from("AUDIO"),
+            GetDelay(x) => format!("LD V{:X}, DT", x),
+            WaitKey(x) => format!("LD V{:X}, K", x),
+            SetDelay(x) => format!("LD DT, V{:X}", x),
+            SetSound(x) => format!("LD ST, V{:X}", x),
+            AddI(x) => format!("ADD I, V{:X}", x),
+            Font(x) => format!("LD F, V{:X}", x),
+            BigFont(x) => format!("LD HF, V{:X}", x),
+            Bcd(x) => format!("LD B, V{:X}", x),
+            Pitch(x) => format!("PITCH V{:X}", x),
+            Store(x) => format!("LD [I], V{:X}", x),
+            Load(x) => format!("LD V{:X}, [I]", x),
+            SaveFlags(x) => format!("LD R, V{:X}", x),
+            LoadFlags(x) => format!("LD V{:X}, R", x),
+        }
+    }
+
+    // Whether the instruction only exists in XO-CHIP
+    pub fn is_xo_chip_only(&self) -> bool {
+        match *self {
+            ScrollUp(_) | StoreRange { .. } | LoadRange { .. } |
+            SetLongI | SelectPlanes(_) | LoadAudio | Pitch(_) => true,
+            // SUPER-CHIP only has 8 flags
+            SaveFlags(x) | LoadFlags(x) => x >= 8,
+            _ => false,
+        }
+    }
+
+    // Whether the instruction may skip the next one
+    pub fn is_skip(&self) -> bool {
+        matches!(*self, SkipEqImm { .. } | SkipNeImm { .. } | SkipEq { .. } | SkipNe { .. } |
+                        SkipKeyDown(_) | SkipKeyUp(_))
+    }
+
+    // Size in bytes, including the 16-bit operand of F000 NNNN
+    pub fn size(&self) -> u16 {
+        match *self {
+            SetLongI => 4,
+            _ => 2,
+        }
+    }
+}
+
+impl fmt::Display for Instruction {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.format_with(&Quirks::default(), |addr| format!("{:03X}", addr)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode() {
+        assert_eq!(Instruction::decode(0x00E0), Ok(ClearScreen));
+        assert_eq!(Instruction::decode(0x1ABC), Ok(Jump(0xABC)));
+        assert_eq!(Instruction::decode(0x7A12), Ok(AddImm { x: 0xA, kk: 0x12 }));
+        assert_eq!(Instruction::decode(0xD125), Ok(Draw { x: 1, y: 2, n: 5 }));
+        assert_eq!(Instruction::decode(0xF301), Ok(SelectPlanes(3)));
+        assert_eq!(Instruction::decode(0x5AB1), Err(DecodeError { word: 0x5AB1 }));
+        assert_eq!(Instruction::decode(0xE1A2), Err(DecodeError { word: 0xE1A2 }));
+    }
+
+    #[test]
+    fn encode_inverts_decode() {
+        for word in 0..=0xFFFF {
+            if let Ok(instruction) = Instruction::decode(word) {
+                assert_eq!(instruction.encode(), word, "{}", instruction);
+            }
+        }
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(Jump(0x2A0).to_string(), "JP 2A0");
+        assert_eq!(AddImm { x: 0xA, kk: 0x12 }.to_string(), "ADD VA, 12");
+        assert_eq!(Store(3).to_string(), "LD [I], V3");
+        assert_eq!(JumpIndexed(0x3A0).to_string(), "JP V0, 3A0");
+        assert_eq!(JumpIndexed(0x3A0).format_with(&Quirks::SUPER_CHIP, |addr| format!("{:03X}", addr)), "JP V3, 3A0");
+    }
+
+    #[test]
+    fn xo_chip_only() {
+        assert!(Instruction::decode(0xF000).unwrap().is_xo_chip_only());
+        assert!(Instruction::decode(0xF875).unwrap().is_xo_chip_only());
+        assert!(!Instruction::decode(0xF775).unwrap().is_xo_chip_only());
+        assert!(!Instruction::decode(0x00C4).unwrap().is_xo_chip_only());
+    }
+}
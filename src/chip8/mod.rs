@@ -3,529 +3,821 @@ mod tests;
 #[cfg(test)]
 mod benchs;
 
+pub mod asm;
+mod audio;
+pub mod debugger;
+pub mod disasm;
 mod display;
+mod error;
+mod instruction;
 mod keyboard;
+mod movie;
+mod quirks;
+mod rewind;
+mod rng;
+mod scheduler;
+mod snapshot;
 pub mod termui;
 
 use byteorder::{BigEndian, ByteOrder};
 use self::display::Chip8Display;
 use self::keyboard::Chip8Keyboard;
-
-// A Chip8 runtime state
-#[derive(Copy)]
-pub struct Chip8State {
-    ram: [u8; 0x1000],              // 4K of memory
+pub use self::audio::{AudioSink, Beep, BellSink, PcmSink, SAMPLE_RATE, WavSink};
+pub use self::error::Chip8Error;
+pub use self::instruction::{DecodeError, Instruction};
+pub use self::movie::{Movie, MovieError, MovieEvent, Playback};
+pub use self::quirks::{LoadStoreQuirk, Quirks};
+pub use self::rewind::Rewind;
+pub use self::rng::{BuiltinRandom, RandomSource, XorShift};
+pub use self::scheduler::{FrameScheduler, instructions_in_frame};
+pub use self::snapshot::SnapshotError;
+
+// Maximum number of nested subroutine calls. The stack is kept apart from RAM, so it
+// can't clash with programs using the whole XO-CHIP address space
+const STACK_DEPTH: usize = 16;
+
+// A Chip8 runtime state, drawing random numbers from 'R'
+#[derive(Clone)]
+pub struct Chip8State<R = BuiltinRandom> {
+    ram: Vec<u8>,                   // 4K of memory (64K in XO-CHIP mode)
     reg: [u8; 16],                  // 16 registers
     i: u16,                         // Memory address register
-    sp: u16,                        // Stack pointer
+    stack: [u16; STACK_DEPTH],      // Return addresses of the calls in progress
+    sp: u8,                         // Stack pointer: number of return addresses in the stack
     t_delay: u8,                    // Delay timer
     t_sound: u8,                    // Sound timer
     pc: u16,                        // Program counter
     display: Chip8Display,          // Display model
     keyboard: Chip8Keyboard,        // Keyboard model
     waiting_for_key: Option<u8>,    // If waiting for key press, holds the Vx to put it. 'None' if not waiting
+    key_to_release: Option<u8>,     // Key to be released to end the wait, with the key wait release quirk
+    quirks: Quirks,                 // Behaviour of ambiguous instructions
+    rpl: [u8; 16],                  // SUPER-CHIP RPL user flags (8, or 16 in XO-CHIP mode)
+    halted: bool,                   // Set once the program exits (00FD)
+    waiting_for_vblank: bool,       // Set by Dxyn with the display wait quirk, until the next frame
+    xo_chip: bool,                  // Whether XO-CHIP extensions are enabled
+    audio_pattern: [u8; 16],        // XO-CHIP audio pattern buffer, 128 1-bit samples
+    audio_pitch: u8,                // XO-CHIP audio pattern playback rate
+    rng: R,                         // Random number generator for Cxkk
 }
 
-// Manual implementation is required since arrays only implement clone for sizes < 32
-impl Clone for Chip8State {
-    fn clone(&self) -> Self {
-        Chip8State{
-            ram: self.ram,
-            reg: self.reg,
-            i: self.i,
-            sp: self.sp,
-            t_delay: self.t_delay,
-            t_sound: self.t_sound,
-            pc: self.pc,
-            display: self.display,
-            keyboard: self.keyboard,
-            waiting_for_key: self.waiting_for_key,
-        }
+impl Chip8State {
+
+    // Create a new Chip8State instance, with an unpredictably seeded generator
+    pub fn new() -> Chip8State {
+        Chip8State::with_random(BuiltinRandom::from_entropy())
+    }
+}
+
+impl Default for Chip8State {
+    fn default() -> Chip8State {
+        Chip8State::new()
     }
 }
 
 // Methods for a Chip8State
-impl Chip8State {
+impl<R: RandomSource> Chip8State<R> {
 
-    // Create a new Chip8State instance
-    pub fn new() -> Chip8State {
+    // Create a new Chip8State instance drawing random numbers from 'rng'
+    pub fn with_random(rng: R) -> Chip8State<R> {
         let mut state = Chip8State {
-            ram: [0; 0x1000],
+            ram: vec![0; 0x1000],
             reg: [0; 16],
             i: 0,
-            sp: 0xEA0,
+            stack: [0; STACK_DEPTH],
+            sp: 0,
             t_delay: 0,
             t_sound: 0,
             pc: 0x200,
             display: Chip8Display::new(),
             keyboard: Chip8Keyboard::new(),
             waiting_for_key: None,
+            key_to_release: None,
+            quirks: Quirks::default(),
+            rpl: [0; 16],
+            halted: false,
+            waiting_for_vblank: false,
+            xo_chip: false,
+            audio_pattern: [0; 16],
+            audio_pitch: 64,
+            rng,
         };
         // load font sprites
         (&mut state.ram[0..FONT_SPRITES.len()]).copy_from_slice(&FONT_SPRITES);
+        let big_font_range = BIG_FONT_ADDR..(BIG_FONT_ADDR + BIG_FONT_SPRITES.len());
+        state.ram[big_font_range].copy_from_slice(&BIG_FONT_SPRITES);
         state
     }
 
     // Load data onto RAM, return new state
-    pub fn load(&self, data: &[u8]) -> Chip8State {
-        let mut new_state = *self;
+    pub fn load(&self, data: &[u8]) -> Chip8State<R> {
+        let mut new_state = self.clone();
         (&mut new_state.ram[0x200..(0x200+data.len())]).copy_from_slice(data);
         new_state
     }
 
+    // Set the behaviour of ambiguous instructions, return new state
+    pub fn with_quirks(&self, quirks: Quirks) -> Chip8State<R> {
+        let mut new_state = self.clone();
+        new_state.quirks = quirks;
+        new_state
+    }
+
+    // Enable or disable the XO-CHIP extensions, return new state
+    pub fn with_xo_chip(&self, enabled: bool) -> Chip8State<R> {
+        let mut new_state = self.clone();
+        new_state.xo_chip = enabled;
+        new_state.ram.resize(if enabled { 0x10000 } else { 0x1000 }, 0);
+        new_state
+    }
+
+    // Size of the addressable memory
+    fn memory_size(&self) -> usize {
+        self.ram.len()
+    }
+
     pub fn get_pixel(&self, col: u8, row: u8) -> bool {
         self.display.get_pixel(col, row)
     }
 
+    // Colour (0-3) of a pixel, made of its value on each of the XO-CHIP planes
+    pub fn get_color(&self, col: u8, row: u8) -> u8 {
+        self.display.get_color(col, row)
+    }
+
+    // A row of pixels of a plane (0, or 1 in XO-CHIP), column 0 being the most
+    // significant bit. In low resolution mode only the 64 most significant bits are used
+    pub fn display_row(&self, plane: usize, row: u8) -> u128 {
+        self.display.row_bits(plane, row as usize)
+    }
+
+    // All the rows of a plane in the current mode, as in 'display_row'
+    pub fn display_plane(&self, plane: usize) -> &[u128] {
+        self.display.plane_rows(plane)
+    }
+
+    // Columns and rows of the display, in its current mode
+    pub fn display_size(&self) -> (u8, u8) {
+        (self.display.cols() as u8, self.display.rows() as u8)
+    }
+
+    // The display as text, a line per row: '.' for unlit pixels, '#' for lit ones
+    // (XO-CHIP: '+' if lit only in the second plane, '@' if lit in both)
+    pub fn screen_to_text(&self) -> String {
+        let (cols, rows) = self.display_size();
+        let mut text = String::new();
+        for row in 0..rows {
+            for col in 0..cols {
+                text.push(match self.get_color(col, row) {
+                    0 => '.',
+                    1 => '#',
+                    2 => '+',
+                    _ => '@',
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    // Whether the program has exited (00FD)
+    pub fn is_halted(&self) -> bool {
+        self.halted
+    }
+
+    // Whether the sound timer is active, so the machine should beep
+    pub fn is_beeping(&self) -> bool {
+        self.t_sound > 0
+    }
+
+    // Whether a Dxyn is waiting for the next frame (display wait quirk). Steps do nothing
+    // until 'vblank' is called
+    pub fn is_waiting_for_vblank(&self) -> bool {
+        self.waiting_for_vblank
+    }
+
     // Read next instruction
-    fn read_instruction(&self) -> Chip8Instruction {
-        let pointer = &self.ram[(self.pc as usize) ..];
-        let word = BigEndian::read_u16(pointer);
-        Chip8Instruction(word)
+    fn read_instruction(&self) -> Result<Chip8Instruction, Chip8Error> {
+        let pc = self.pc as usize;
+        if pc + 2 > self.memory_size() {
+            return Err(Chip8Error::PcOutOfRange { pc: self.pc });
+        }
+        let word = BigEndian::read_u16(&self.ram[pc..]);
+        Ok(Chip8Instruction(word))
     }
 
-    pub fn decrease_timers(&self) -> Self {
-        let mut new_state = *self;
-        if new_state.t_delay > 0 {
-            new_state.t_delay -= 1;
+    // Read the word at 'addr', if inside RAM
+    fn word_at(&self, addr: u16) -> Option<u16> {
+        let addr = addr as usize;
+        if addr + 2 > self.memory_size() {
+            return None;
+        }
+        Some(BigEndian::read_u16(&self.ram[addr..]))
+    }
+
+    // Return addresses in the call stack, from the outermost call to the innermost
+    fn call_stack(&self) -> Vec<u16> {
+        self.stack[..self.sp as usize].to_vec()
+    }
+
+    // Check that 'len' bytes starting at 'addr' are inside RAM
+    fn check_ram_range(&self, addr: usize, len: usize) -> Result<(), Chip8Error> {
+        if addr + len > self.memory_size() {
+            return Err(Chip8Error::MemoryOutOfRange { pc: self.pc, addr: addr + len - 1 });
         }
-        if new_state.t_sound > 0 {
-            new_state.t_sound -= 1;
+        Ok(())
+    }
+
+    // Length of the instruction the PC points to: XO-CHIP's F000 NNNN takes 4 bytes
+    fn instruction_length(&self) -> u16 {
+        let pc = self.pc as usize;
+        if self.xo_chip && pc + 2 <= self.memory_size() && BigEndian::read_u16(&self.ram[pc..]) == 0xF000 {
+            4
+        } else {
+            2
         }
+    }
+
+    pub fn decrease_timers(&self) -> Self {
+        let mut new_state = self.clone();
+        new_state.tick_timers();
         new_state
     }
 
-    // Execute next instruction, returning a new state
-    pub fn exec_instruction(&self) -> Chip8State {
-        let op = self.read_instruction();
-        let nibbles = [op.nibble(1), op.nibble(2), op.nibble(3), op.nibble(4)];
-        match &nibbles {
-            // 00E0: Clear screen
-            &[0,0,0xE,0] => self.clear_screen(),
-            // 00EE: Return from subroutine
-            &[0,0,0xE,0xE] => self.return_from_subroutine(),
-            // 1nnn: Jump to 'nnn' address
-            &[1,n1,n2,n3] => self.jump_to(u16_from_nibbles(0, n1, n2, n3)),
-            // 2nnn: Call subroutine at 'nnn'
-            &[2,n1,n2,n3] => self.call_subroutine(u16_from_nibbles(0, n1, n2, n3)),
-            // 3xkk: Skip next instruction if Vx == kk
-            &[3,x,k1,k2] => self.skip_if_equals_immediate(x, u8_from_nibbles(k1, k2)),
-            // 4xkk: Skip next instruction if Vx != kk
-            &[4,x,k1,k2] => self.skip_if_not_equals_immediate(x, u8_from_nibbles(k1, k2)),
-            // 5xy0: Skip next instruction if Vx = Vy
-            &[5,x,y,0] => self.skip_if_registers_equal(x, y),
-            // 6xkk: Put the value kk into register Vx
-            &[6,x,k1,k2] => self.load_immediate(x, u8_from_nibbles(k1, k2)),
-            // 7xkk: Add the value kk to register Vx
-            &[7,x,k1,k2] => self.add_immediate(x, u8_from_nibbles(k1, k2)),
-            // 8xy0: Store the value of register Vy in register Vx
-            &[8,x,y,0] => self.move_register(x, y),
-            // 8xy1: Performs a bitwise OR on the values of Vx and Vy, then stores the result in Vx
-            &[8,x,y,1] => self.or_registers(x, y),
-            // 8xy2: Performs a bitwise AND on the values of Vx and Vy, then stores the result in Vx
-            &[8,x,y,2] => self.and_registers(x, y),
-            // 8xy3: Performs a bitwise XOR on the values of Vx and Vy, then stores the result in Vx
-            &[8,x,y,3] => self.xor_registers(x, y),
-            // 8xy4: Set Vx = Vx + Vy, set VF = carry
-            &[8,x,y,4] => self.add_registers(x, y),
-            // 8xy5: Set Vx = Vx - Vy, set VF = (Vx>Vy)
-            &[8,x,y,5] => self.sub_registers(x, y),
-            // 8xy6: Set Vx = Vx SHR 1
-            &[8,x,_,6] => self.shr_register(x),
-            // 8xy7: Set Vx = Vy - Vx, set VF = (Vy>Vx)
-            &[8,x,y,7] => self.subn_registers(x, y),
-            // 8xyE: Set Vx = Vx SHL 1
-            &[8,x,_,0xE] => self.shl_register(x),
-            // 9xy0: Skip next instruction if Vx != Vy
-            &[9,x,y,0] => self.skip_if_registers_not_equal(x, y),
-            // Annn: Set I = nnn
-            &[0xA,n1,n2,n3] => self.set_i(u16_from_nibbles(0, n1, n2, n3)),
-            // Bnnn: Jump to location nnn + V0
-            &[0xB,n1,n2,n3] => self.indexed_jump(u16_from_nibbles(0, n1, n2, n3)),
-            // Cxkk: Set Vx = random byte AND kk
-            &[0xC,x,k1,k2] => self.masked_random(x, u8_from_nibbles(k1, k2)),
-            // Dxyn: Display n-byte sprite starting at memory location I at (Vx, Vy)
-            &[0xD,x,y,n] => self.draw_sprite(x, y, n),
-            // Ex9E: Skip next instruction if key with the value of Vx is pressed
-            &[0xE,x,9,0xE] => self.skip_if_key_down(x),
-            // ExA1: Skip next instruction if key with the value of Vx is NOT pressed
-            &[0xE,x,0xA,0x1] => self.skip_if_key_up(x),
-            // Fx07: Skip next instruction if key with the value of Vx is NOT pressed
-            &[0xF,x,0,7] => self.move_delay_timer_value_to_register(x),
-            // Fx0A: Wait for a key press, store the value of the key in Vx
-            &[0xF,x,0,0xA] => self.wait_for_key(x),
-            // Fx15: Set delay timer = Vx
-            &[0xF,x,1,5] => self.set_delay_timer(x),
-            // Fx18: Set delay timer = Vx
-            &[0xF,x,1,8] => self.set_sound_timer(x),
-            // Fx1E: Set I = I + Vx
-            &[0xF,x,1,0xE] => self.add_register_to_i(x),
-            // Fx29: Set I = location of sprite for digit Vx
-            &[0xF,x,2,9] => self.set_sprite_location(x),
-            // Fx33: Store BCD representation of Vx in memory locations I, I+1, and I+2
-            &[0xF,x,3,3] => self.binary_coded_decimal_conversion(x),
-            // Fx55: Store registers V0 through Vx in memory starting at location I
-            &[0xF,x,5,5] => self.dump_registers_up_to(x),
-            // Fx65: Read registers V0 through Vx from memory starting at location I
-            &[0xF,x,6,5] => self.load_registers_up_to(x),
-            // Panic if unknown
-            _ => panic!("Unknown instruction: {:?}", op)
+    fn tick_timers(&mut self) {
+        if self.t_delay > 0 {
+            self.t_delay -= 1;
+        }
+        if self.t_sound > 0 {
+            self.t_sound -= 1;
         }
     }
 
-    fn clear_screen(&self) -> Chip8State {
-        let mut new_state = *self;
-        new_state.display = display::Chip8Display::new();
-        new_state.pc += 2;
+    // The 60Hz vertical blank interrupt, to be called at the end of each frame: timers
+    // decrease, and a Dxyn waiting for it lets the program go on. Returns a new state
+    pub fn vblank(&self) -> Self {
+        let mut new_state = self.clone();
+        new_state.end_frame();
         new_state
     }
 
-    fn return_from_subroutine(&self) -> Chip8State {
+    // Same as 'vblank', updating this state
+    pub fn end_frame(&mut self) {
+        self.tick_timers();
+        self.waiting_for_vblank = false;
+    }
+
+    // Execute next instruction, returning a new state. Copies the whole machine, so 'step'
+    // is faster when the previous state isn't needed
+    pub fn exec_instruction(&self) -> Result<Chip8State<R>, Chip8Error> {
+        let mut new_state = self.clone();
+        new_state.step()?;
+        Ok(new_state)
+    }
+
+    // Execute next instruction, updating this state
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        if self.halted || self.waiting_for_vblank {
+            return Ok(());
+        }
+        let op = self.read_instruction()?;
+        let instruction = match Instruction::decode(op.0) {
+            Ok(instruction) if self.xo_chip || !instruction.is_xo_chip_only() => instruction,
+            // Fail if unknown
+            _ => return Err(Chip8Error::UnknownOpcode { opcode: op.0, addr: self.pc }),
+        };
+        match instruction {
+            Instruction::ClearScreen => self.clear_screen(),
+            Instruction::Return => self.return_from_subroutine()?,
+            Instruction::ScrollDown(n) => self.scroll_down(n),
+            Instruction::ScrollUp(n) => self.scroll_up(n),
+            Instruction::ScrollRight => self.scroll_right(),
+            Instruction::ScrollLeft => self.scroll_left(),
+            Instruction::Exit => self.exit(),
+            Instruction::LowRes => self.set_hires(false),
+            Instruction::HighRes => self.set_hires(true),
+            Instruction::Jump(addr) => self.jump_to(addr),
+            Instruction::Call(addr) => self.call_subroutine(addr)?,
+            Instruction::SkipEqImm { x, kk } => self.skip_if_equals_immediate(x, kk),
+            Instruction::SkipNeImm { x, kk } => self.skip_if_not_equals_immediate(x, kk),
+            Instruction::SkipEq { x, y } => self.skip_if_registers_equal(x, y),
+            Instruction::StoreRange { x, y } => self.dump_register_range(x, y)?,
+            Instruction::LoadRange { x, y } => self.load_register_range(x, y)?,
+            Instruction::LoadImm { x, kk } => self.load_immediate(x, kk),
+            Instruction::AddImm { x, kk } => self.add_immediate(x, kk),
+            Instruction::Move { x, y } => self.move_register(x, y),
+            Instruction::Or { x, y } => self.or_registers(x, y),
+            Instruction::And { x, y } => self.and_registers(x, y),
+            Instruction::Xor { x, y } => self.xor_registers(x, y),
+            Instruction::Add { x, y } => self.add_registers(x, y),
+            Instruction::Sub { x, y } => self.sub_registers(x, y),
+            Instruction::Shr { x, y } => self.shr_register(x, y),
+            Instruction::SubN { x, y } => self.subn_registers(x, y),
+            Instruction::Shl { x, y } => self.shl_register(x, y),
+            Instruction::SkipNe { x, y } => self.skip_if_registers_not_equal(x, y),
+            Instruction::SetI(addr) => self.set_i(addr),
+            Instruction::JumpIndexed(addr) => self.indexed_jump((addr >> 8) as u8, addr),
+            Instruction::Random { x, kk } => self.masked_random(x, kk),
+            Instruction::Draw { x, y, n: 0 } => self.draw_wide_sprite(x, y)?,
+            Instruction::Draw { x, y, n } => self.draw_sprite(x, y, n)?,
+            Instruction::SkipKeyDown(x) => self.skip_if_key_down(x),
+            Instruction::SkipKeyUp(x) => self.skip_if_key_up(x),
+            Instruction::SetLongI => self.set_long_i()?,
+            Instruction::SelectPlanes(n) => self.select_planes(n),
+            Instruction::LoadAudio => self.load_audio_pattern()?,
+            Instruction::GetDelay(x) => self.move_delay_timer_value_to_register(x),
+            Instruction::WaitKey(x) => self.wait_for_key(x),
+            Instruction::SetDelay(x) => self.set_delay_timer(x),
+            Instruction::SetSound(x) => self.set_sound_timer(x),
+            Instruction::AddI(x) => self.add_register_to_i(x),
+            Instruction::Font(x) => self.set_sprite_location(x),
+            Instruction::BigFont(x) => self.set_big_sprite_location(x),
+            Instruction::Bcd(x) => self.binary_coded_decimal_conversion(x)?,
+            Instruction::Pitch(x) => self.set_audio_pitch(x),
+            Instruction::Store(x) => self.dump_registers_up_to(x)?,
+            Instruction::Load(x) => self.load_registers_up_to(x)?,
+            Instruction::SaveFlags(x) => self.save_flags_up_to(x),
+            Instruction::LoadFlags(x) => self.load_flags_up_to(x),
+        }
+        Ok(())
+    }
+
+    fn clear_screen(&mut self) {
+        self.display.clear();
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    fn scroll_down(&mut self, n: u8) {
+        self.display.scroll_down(n as usize);
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    fn scroll_up(&mut self, n: u8) {
+        self.display.scroll_up(n as usize);
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    fn scroll_right(&mut self) {
+        self.display.scroll_right(4);
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    fn scroll_left(&mut self) {
+        self.display.scroll_left(4);
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    // Stop the program. PC is left pointing to the 00FD instruction
+    fn exit(&mut self) {
+        self.halted = true;
+    }
+
+    fn set_hires(&mut self, hires: bool) {
+        self.display.set_hires(hires);
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    fn return_from_subroutine(&mut self) -> Result<(), Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow { addr: self.pc });
+        }
         let ret_addr = self.read_return_address();
-        let mut new_state = *self;
-        new_state.pc = ret_addr;
-        new_state.sp -= 2;
-        new_state
+        self.pc = ret_addr;
+        self.sp -= 1;
+        Ok(())
     }
 
-    // Read the return address the SP points to
+    // Read the return address on top of the stack
     fn read_return_address(&self) -> u16 {
-        BigEndian::read_u16(&self.ram[(self.sp as usize)..])
+        self.stack[self.sp as usize - 1]
     }
 
-    fn jump_to(&self, addr: u16) -> Chip8State {
-        let mut new_state = *self;
-        new_state.pc = addr;
-        new_state
+    fn jump_to(&mut self, addr: u16) {
+        self.pc = addr;
     }
 
-    fn indexed_jump(&self, addr: u16) -> Chip8State {
-        let mut new_state = *self;
-        new_state.pc = addr + new_state.reg[0] as u16;
-        new_state
+    fn indexed_jump(&mut self, x: u8, addr: u16) {
+        let offset_reg = if self.quirks.jump_uses_vx { x } else { 0 };
+        self.pc = addr + self.reg[offset_reg as usize] as u16;
     }
 
-    fn call_subroutine(&self, subroutine_addr: u16) -> Chip8State {
-        let mut new_state = *self;
+    fn call_subroutine(&mut self, subroutine_addr: u16) -> Result<(), Chip8Error> {
+        if self.sp as usize == STACK_DEPTH {
+            return Err(Chip8Error::StackOverflow { addr: self.pc });
+        }
         // stack return address
-        let return_address = self.pc + 2;
-        new_state.sp += 2;
-        new_state.ram[new_state.sp as usize] = (return_address >> 8) as u8;
-        new_state.ram[(new_state.sp + 1) as usize] = (return_address & 0xFF) as u8;
+        self.stack[self.sp as usize] = self.pc.wrapping_add(2);
+        self.sp += 1;
         // set PC
-        new_state.pc = subroutine_addr;
-        new_state
+        self.pc = subroutine_addr;
+        Ok(())
     }
 
-    fn skip_if_equals_immediate(&self, reg_index: u8, value: u8) -> Chip8State {
-        let mut new_state = *self;
+    fn skip_if_equals_immediate(&mut self, reg_index: u8, value: u8) {
         let reg_value = self.reg[reg_index as usize];
         // Increment PC, once more if values equal
-        new_state.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         if reg_value == value {
-            new_state.pc += 2;
+            self.pc = self.pc.wrapping_add(self.instruction_length());
         }
-        new_state
     }
 
-    fn skip_if_not_equals_immediate(&self, reg_index: u8, value: u8) -> Chip8State {
-        let mut new_state = *self;
+    fn skip_if_not_equals_immediate(&mut self, reg_index: u8, value: u8) {
         let reg_value = self.reg[reg_index as usize];
         // Increment PC, once more if values not equal
-        new_state.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         if reg_value != value {
-            new_state.pc += 2;
+            self.pc = self.pc.wrapping_add(self.instruction_length());
         }
-        new_state
     }
 
-    fn skip_if_registers_equal(&self, vx: u8, vy: u8) -> Chip8State {
-        let mut new_state = *self;
+    fn skip_if_registers_equal(&mut self, vx: u8, vy: u8) {
         let vx_value = self.reg[vx as usize];
         let vy_value = self.reg[vy as usize];
         // Increment PC, once more if values equal
-        new_state.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         if vx_value == vy_value {
-            new_state.pc += 2;
+            self.pc = self.pc.wrapping_add(self.instruction_length());
         }
-        new_state
     }
 
-    fn skip_if_registers_not_equal(&self, vx: u8, vy: u8) -> Chip8State {
-        let mut new_state = *self;
+    fn skip_if_registers_not_equal(&mut self, vx: u8, vy: u8) {
         let vx_value = self.reg[vx as usize];
         let vy_value = self.reg[vy as usize];
         // Increment PC, once more if values not equal
-        new_state.pc += 2;
+        self.pc = self.pc.wrapping_add(2);
         if vx_value != vy_value {
-            new_state.pc += 2;
+            self.pc = self.pc.wrapping_add(self.instruction_length());
         }
-        new_state
     }
 
-    fn load_immediate(&self, reg_index: u8, value: u8) -> Chip8State {
-        let mut new_state = *self;
-        new_state.reg[reg_index as usize] = value;
-        new_state.pc += 2;
-        new_state
+    fn load_immediate(&mut self, reg_index: u8, value: u8) {
+        self.reg[reg_index as usize] = value;
+        self.pc = self.pc.wrapping_add(2);
     }
 
-    fn add_immediate(&self, reg_index: u8, value: u8) -> Chip8State {
-        let mut new_state = *self;
-        let current = new_state.reg[reg_index as usize];
+    fn add_immediate(&mut self, reg_index: u8, value: u8) {
+        let current = self.reg[reg_index as usize];
         let (sum, _) = current.overflowing_add(value);
-        new_state.reg[reg_index as usize] = sum;
-        new_state.pc += 2;
-        new_state
+        self.reg[reg_index as usize] = sum;
+        self.pc = self.pc.wrapping_add(2);
     }
 
-    fn move_register(&self, vx: u8, vy: u8) -> Chip8State {
-        let mut new_state = *self;
-        new_state.reg[vx as usize] = new_state.reg[vy as usize];
-        new_state.pc += 2;
-        new_state
+    fn move_register(&mut self, vx: u8, vy: u8) {
+        self.reg[vx as usize] = self.reg[vy as usize];
+        self.pc = self.pc.wrapping_add(2);
     }
 
-    fn or_registers(&self, vx: u8, vy: u8) -> Chip8State {
-        let mut new_state = *self;
-        new_state.reg[vx as usize] |= new_state.reg[vy as usize];
-        new_state.pc += 2;
-        new_state
+    fn or_registers(&mut self, vx: u8, vy: u8) {
+        self.reg[vx as usize] |= self.reg[vy as usize];
+        if self.quirks.logic_resets_vf {
+            self.reg[0xF] = 0;
+        }
+        self.pc = self.pc.wrapping_add(2);
     }
 
-    fn and_registers(&self, vx: u8, vy: u8) -> Chip8State {
-        let mut new_state = *self;
-        new_state.reg[vx as usize] &= new_state.reg[vy as usize];
-        new_state.pc += 2;
-        new_state
+    fn and_registers(&mut self, vx: u8, vy: u8) {
+        self.reg[vx as usize] &= self.reg[vy as usize];
+        if self.quirks.logic_resets_vf {
+            self.reg[0xF] = 0;
+        }
+        self.pc = self.pc.wrapping_add(2);
     }
 
-    fn xor_registers(&self, vx: u8, vy: u8) -> Chip8State {
-        let mut new_state = *self;
-        new_state.reg[vx as usize] ^= new_state.reg[vy as usize];
-        new_state.pc += 2;
-        new_state
+    fn xor_registers(&mut self, vx: u8, vy: u8) {
+        self.reg[vx as usize] ^= self.reg[vy as usize];
+        if self.quirks.logic_resets_vf {
+            self.reg[0xF] = 0;
+        }
+        self.pc = self.pc.wrapping_add(2);
     }
 
     // The values of Vx and Vy are added together. If the result is greater than 8 bits (>255)
     // VF is set to 1, otherwise 0. Only the lowest 8 bits of the result are kept, and stored in Vx
-    fn add_registers(&self, vx: u8, vy: u8) -> Chip8State {
-        let mut new_state = *self;
-        let r1 = new_state.reg[vx as usize];
-        let r2 = new_state.reg[vy as usize];
+    fn add_registers(&mut self, vx: u8, vy: u8) {
+        let r1 = self.reg[vx as usize];
+        let r2 = self.reg[vy as usize];
         let (sum, carry) = r1.overflowing_add(r2);
-        new_state.reg[vx as usize] = sum;
-        new_state.reg[0xF] = carry as u8;
-        new_state.pc += 2;
-        new_state
+        self.reg[vx as usize] = sum;
+        self.reg[0xF] = carry as u8;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     // If Vx > Vy, then VF is set to 1, otherwise 0. Then Vy is subtracted from Vx,
     // and the results stored in Vx
-    fn sub_registers(&self, vx: u8, vy: u8) -> Chip8State {
-        let mut new_state = *self;
-        let x = new_state.reg[vx as usize];
-        let y = new_state.reg[vy as usize];
-        new_state.reg[0xF] = (x > y) as u8;
+    fn sub_registers(&mut self, vx: u8, vy: u8) {
+        let x = self.reg[vx as usize];
+        let y = self.reg[vy as usize];
         let (sub, _) = x.overflowing_sub(y);
-        new_state.reg[vx as usize] = sub;
-        new_state.pc += 2;
-        new_state
+        self.reg[vx as usize] = sub;
+        // the flag is written last, so it wins when Vx is VF
+        self.reg[0xF] = (x > y) as u8;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     // If Vy > Vx, then VF is set to 1, otherwise 0. Then Vx is subtracted from Vy,
     // and the results stored in Vx
-    fn subn_registers(&self, vx: u8, vy: u8) -> Chip8State {
-        let mut new_state = *self;
-        let x = new_state.reg[vx as usize];
-        let y = new_state.reg[vy as usize];
-        new_state.reg[0xF] = (y > x) as u8;
+    fn subn_registers(&mut self, vx: u8, vy: u8) {
+        let x = self.reg[vx as usize];
+        let y = self.reg[vy as usize];
         let (sub, _) = y.overflowing_sub(x);
-        new_state.reg[vx as usize] = sub;
-        new_state.pc += 2;
-        new_state
+        self.reg[vx as usize] = sub;
+        self.reg[0xF] = (y > x) as u8;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     // VF is set to the least-significant bit of Vx. Then Vx is divided by 2.
-    fn shr_register(&self, vx: u8) -> Chip8State {
-        let mut new_state = *self;
-        let x = new_state.reg[vx as usize];
-        new_state.reg[0xF] = x & 1;
-        new_state.reg[vx as usize] = x >> 1;
-        new_state.pc += 2;
-        new_state
+    // With the 'shift_uses_vy' quirk, Vy is shifted instead and the result stored in Vx
+    fn shr_register(&mut self, vx: u8, vy: u8) {
+        let x = self.reg[self.shift_source(vx, vy) as usize];
+        self.reg[vx as usize] = x >> 1;
+        self.reg[0xF] = x & 1;
+        self.pc = self.pc.wrapping_add(2);
     }
 
     // VF is set to the most-significant bit of Vx. Then Vx is multiplied by 2.
-    fn shl_register(&self, vx: u8) -> Chip8State {
-        let mut new_state = *self;
-        let x = new_state.reg[vx as usize];
-        new_state.reg[0xF] = x >> 7;
-        new_state.reg[vx as usize] = x << 1;
-        new_state.pc += 2;
-        new_state
+    // With the 'shift_uses_vy' quirk, Vy is shifted instead and the result stored in Vx
+    fn shl_register(&mut self, vx: u8, vy: u8) {
+        let x = self.reg[self.shift_source(vx, vy) as usize];
+        self.reg[vx as usize] = x << 1;
+        self.reg[0xF] = x >> 7;
+        self.pc = self.pc.wrapping_add(2);
     }
 
-    fn set_i(&self, n: u16) -> Chip8State {
-        let mut new_state = *self;
-        new_state.i = n;
-        new_state.pc += 2;
-        new_state
+    // Register shifted by 8xy6/8xyE
+    fn shift_source(&self, vx: u8, vy: u8) -> u8 {
+        if self.quirks.shift_uses_vy { vy } else { vx }
     }
 
-    fn masked_random(&self, vx: u8, mask: u8) -> Chip8State {
-        let mut new_state = *self;
-        let random = ::rand::random::<u8>();
-        new_state.reg[vx as usize] = random & mask;
-        new_state.pc += 2;
-        new_state
+    fn set_i(&mut self, n: u16) {
+        self.i = n;
+        self.pc = self.pc.wrapping_add(2);
     }
 
-    fn draw_sprite(&self, x: u8, y: u8, n: u8) -> Chip8State {
-        let mut new_state = *self;
+    fn masked_random(&mut self, vx: u8, mask: u8) {
+        let random = self.rng.next_byte();
+        self.reg[vx as usize] = random & mask;
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    fn draw_sprite(&mut self, x: u8, y: u8, n: u8) -> Result<(), Chip8Error> {
+        // there's a sprite for each selected plane
+        let size = n as usize * self.display.selected_planes();
+        self.check_ram_range(self.i as usize, size)?;
         // get coordinates
-        let col = new_state.reg[x as usize];
-        let row = new_state.reg[y as usize];
+        let col = self.reg[x as usize];
+        let row = self.reg[y as usize];
         // load sprite
-        let sprite_begin = new_state.i as usize;
-        let sprite_end = (new_state.i+(n as u16)) as usize;
-        let sprite = &new_state.ram[sprite_begin..sprite_end];
+        let sprite_begin = self.i as usize;
+        let sprite_end = sprite_begin + size;
+        let sprite = &self.ram[sprite_begin..sprite_end];
         // draw
-        let collision = new_state.display.draw_sprite(col, row, sprite);
+        let collision = self.display.draw_sprite(col, row, sprite, self.quirks.wrap_sprites);
         // set vF
-        new_state.reg[0xF] = collision as u8;
+        self.reg[0xF] = collision as u8;
         // increase PC
-        new_state.pc += 2;
-        new_state
+        self.pc = self.pc.wrapping_add(2);
+        self.waiting_for_vblank = self.quirks.display_wait;
+        Ok(())
+    }
+
+    fn draw_wide_sprite(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let size = 32 * self.display.selected_planes();
+        self.check_ram_range(self.i as usize, size)?;
+        let col = self.reg[x as usize];
+        let row = self.reg[y as usize];
+        let sprite_begin = self.i as usize;
+        let sprite = &self.ram[sprite_begin..(sprite_begin + size)];
+        let collision = self.display.draw_wide_sprite(col, row, sprite, self.quirks.wrap_sprites);
+        self.reg[0xF] = collision as u8;
+        self.pc = self.pc.wrapping_add(2);
+        self.waiting_for_vblank = self.quirks.display_wait;
+        Ok(())
+    }
+
+    fn set_long_i(&mut self) -> Result<(), Chip8Error> {
+        let addr_location = self.pc as usize + 2;
+        if addr_location + 2 > self.memory_size() {
+            return Err(Chip8Error::PcOutOfRange { pc: self.pc });
+        }
+        self.i = BigEndian::read_u16(&self.ram[addr_location..]);
+        self.pc = self.pc.wrapping_add(4);
+        Ok(())
+    }
+
+    fn select_planes(&mut self, n: u8) {
+        self.display.select_planes(n);
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    fn load_audio_pattern(&mut self) -> Result<(), Chip8Error> {
+        self.check_ram_range(self.i as usize, 16)?;
+        let begin = self.i as usize;
+        self.audio_pattern.copy_from_slice(&self.ram[begin..(begin + 16)]);
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    fn set_audio_pitch(&mut self, x: u8) {
+        self.audio_pitch = self.reg[x as usize];
+        self.pc = self.pc.wrapping_add(2);
     }
 
-    fn skip_if_key_down(&self, x: u8) -> Chip8State {
-        let mut new_state = *self;
-        let key = new_state.reg[x as usize];
-        new_state.pc += 2;
-        if new_state.is_key_down(key) {
-            new_state.pc += 2;
+    fn skip_if_key_down(&mut self, x: u8) {
+        let key = self.reg[x as usize];
+        self.pc = self.pc.wrapping_add(2);
+        if self.is_key_down(key) {
+            self.pc = self.pc.wrapping_add(self.instruction_length());
         }
-        new_state
     }
 
-    fn skip_if_key_up(&self, x: u8) -> Chip8State {
-        let mut new_state = *self;
-        let key = new_state.reg[x as usize];
-        new_state.pc += 2;
-        if !new_state.is_key_down(key) {
-            new_state.pc += 2;
+    fn skip_if_key_up(&mut self, x: u8) {
+        let key = self.reg[x as usize];
+        self.pc = self.pc.wrapping_add(2);
+        if !self.is_key_down(key) {
+            self.pc = self.pc.wrapping_add(self.instruction_length());
         }
-        new_state
     }
 
     pub fn is_key_down(&self, key: u8) -> bool {
         self.keyboard.is_key_pressed(key)
     }
 
-    // handler for 'key pressed' events
-    pub fn key_down(&mut self, key: u8) -> Chip8State {
-        let mut new_state = *self;
-        new_state.keyboard.key_pressed(key);
-        match new_state.waiting_for_key {
-            Some(x) => {
-                new_state.reg[x as usize] = key;
-                new_state.pc += 2;
-                new_state.waiting_for_key = None;
+    // handler for 'key pressed' events, returning a new state
+    pub fn key_down(&mut self, key: u8) -> Chip8State<R> {
+        let mut new_state = self.clone();
+        new_state.press_key(key);
+        new_state
+    }
+
+    // handler for 'key released' events, returning a new state
+    pub fn key_up(&mut self, key: u8) -> Chip8State<R> {
+        let mut new_state = self.clone();
+        new_state.release_key(key);
+        new_state
+    }
+
+    // Same as 'key_down', updating this state. Only a key that wasn't down already counts for Fx0A
+    pub fn press_key(&mut self, key: u8) {
+        let was_down = self.keyboard.is_key_pressed(key);
+        self.keyboard.key_pressed(key);
+        match (self.waiting_for_key, self.key_to_release) {
+            (Some(_), None) if !was_down && self.quirks.key_wait_release => {
+                self.key_to_release = Some(key);
             }
+            (Some(x), None) if !was_down => self.end_key_wait(x, key),
             _ => {}
         }
-        new_state
     }
 
-    // handler for 'key released' events
-    pub fn key_up(&mut self, key: u8) -> Chip8State {
-        let mut new_state = *self;
-        new_state.keyboard.key_released(key);
-        new_state
+    // Same as 'key_up', updating this state
+    pub fn release_key(&mut self, key: u8) {
+        self.keyboard.key_released(key);
+        match (self.waiting_for_key, self.key_to_release) {
+            (Some(x), Some(pressed)) if pressed == key => self.end_key_wait(x, key),
+            _ => {}
+        }
     }
 
-    fn move_delay_timer_value_to_register(&self, x: u8) -> Chip8State {
-        let mut new_state = *self;
-        new_state.reg[x as usize] = new_state.t_delay;
-        new_state.pc += 2;
-        new_state
+    // Put the key Fx0A was waiting for in Vx and go on
+    fn end_key_wait(&mut self, x: u8, key: u8) {
+        self.reg[x as usize] = key;
+        self.pc = self.pc.wrapping_add(2);
+        self.waiting_for_key = None;
+        self.key_to_release = None;
     }
 
-    fn wait_for_key(&self, x: u8) -> Chip8State {
-        let mut new_state = *self;
-        new_state.waiting_for_key = Some(x);
-        new_state
+    fn move_delay_timer_value_to_register(&mut self, x: u8) {
+        self.reg[x as usize] = self.t_delay;
+        self.pc = self.pc.wrapping_add(2);
     }
 
-    fn set_delay_timer(&self, x: u8) -> Chip8State {
-        let mut new_state = *self;
-        new_state.t_delay = new_state.reg[x as usize];
-        new_state.pc += 2;
-        new_state
+    fn wait_for_key(&mut self, x: u8) {
+        self.waiting_for_key = Some(x);
     }
 
-    fn set_sound_timer(&self, x: u8) -> Self {
-        let mut new_state = *self;
-        new_state.t_sound = new_state.reg[x as usize];
-        new_state.pc += 2;
-        new_state
+    fn set_delay_timer(&mut self, x: u8) {
+        self.t_delay = self.reg[x as usize];
+        self.pc = self.pc.wrapping_add(2);
     }
 
-    fn add_register_to_i(&self, x: u8) -> Self {
-        let mut new_state = *self;
-        let vx = new_state.reg[x as usize];
-        new_state.i += vx as u16;
-        new_state.pc += 2;
-        new_state
+    fn set_sound_timer(&mut self, x: u8) {
+        self.t_sound = self.reg[x as usize];
+        self.pc = self.pc.wrapping_add(2);
     }
 
-    fn set_sprite_location(&self, x: u8) -> Self {
-        let mut new_state = *self;
-        let vx = new_state.reg[x as usize];
-        new_state.i = (vx as u16) * 5;
-        new_state.pc += 2;
-        new_state
+    fn add_register_to_i(&mut self, x: u8) {
+        let vx = self.reg[x as usize];
+        self.i = self.i.wrapping_add(vx as u16);
+        // overflow is past the 12-bit address space, which XO-CHIP extends to 16 bits
+        if self.quirks.i_overflow_sets_vf && !self.xo_chip {
+            self.reg[0xF] = (self.i > 0xFFF) as u8;
+        }
+        self.pc = self.pc.wrapping_add(2);
     }
 
-    fn binary_coded_decimal_conversion(&self, x: u8) -> Self {
-        let mut new_state = *self;
-        let target_address = new_state.i as usize;
-        let vx = new_state.reg[x as usize];
+    fn set_sprite_location(&mut self, x: u8) {
+        let vx = self.reg[x as usize];
+        self.i = (vx as u16) * 5;
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    fn set_big_sprite_location(&mut self, x: u8) {
+        let vx = self.reg[x as usize] & 0xF;
+        self.i = BIG_FONT_ADDR as u16 + (vx as u16) * 10;
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    fn binary_coded_decimal_conversion(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_ram_range(self.i as usize, 3)?;
+        let target_address = self.i as usize;
+        let vx = self.reg[x as usize];
         let units = vx % 10;
         let tens = (vx / 10) % 10;
         let hundreds = (vx / 100) % 10;
-        new_state.ram[target_address] = hundreds;
-        new_state.ram[target_address+1] = tens;
-        new_state.ram[target_address+2] = units;
-        new_state.pc += 2;
-        new_state
+        self.ram[target_address] = hundreds;
+        self.ram[target_address+1] = tens;
+        self.ram[target_address+2] = units;
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
     }
 
-    fn dump_registers_up_to(&self, x: u8) -> Self {
-        let mut new_state = *self;
+    fn dump_registers_up_to(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_ram_range(self.i as usize, 1 + x as usize)?;
         {
-            let target_address = new_state.i as usize;
+            let target_address = self.i as usize;
             let target_ram_range = target_address..(1 + target_address + x as usize);
-            let target_ram_slice = &mut new_state.ram[target_ram_range];
-            let regs_slice = &new_state.reg[0..(1 + x as usize)];
+            let target_ram_slice = &mut self.ram[target_ram_range];
+            let regs_slice = &self.reg[0..(1 + x as usize)];
             target_ram_slice.copy_from_slice(regs_slice);
         }
-        new_state.pc += 2;
-        new_state
+        self.i = self.i_after_load_store(x);
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
     }
 
-    fn load_registers_up_to(&self, x: u8) -> Self {
-        let mut new_state = *self;
+    fn load_registers_up_to(&mut self, x: u8) -> Result<(), Chip8Error> {
+        self.check_ram_range(self.i as usize, 1 + x as usize)?;
         {
-            let source_address = new_state.i as usize;
+            let source_address = self.i as usize;
             let source_ram_range = source_address..(1 + source_address + x as usize);
-            let source_ram_slice = &new_state.ram[source_ram_range];
-            let regs_slice = &mut new_state.reg[0..(1 + x as usize)];
+            let source_ram_slice = &self.ram[source_ram_range];
+            let regs_slice = &mut self.reg[0..(1 + x as usize)];
             regs_slice.copy_from_slice(source_ram_slice);
         }
-        new_state.pc += 2;
-        new_state
+        self.i = self.i_after_load_store(x);
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    // Registers Vx through Vy, in that order (which is descending if x > y)
+    fn register_range(x: u8, y: u8) -> Vec<usize> {
+        if x <= y {
+            (x as usize..(y as usize + 1)).collect()
+        } else {
+            (y as usize..(x as usize + 1)).rev().collect()
+        }
+    }
+
+    fn dump_register_range(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let regs = Self::register_range(x, y);
+        self.check_ram_range(self.i as usize, regs.len())?;
+        for (offset, r) in regs.into_iter().enumerate() {
+            self.ram[self.i as usize + offset] = self.reg[r];
+        }
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    fn load_register_range(&mut self, x: u8, y: u8) -> Result<(), Chip8Error> {
+        let regs = Self::register_range(x, y);
+        self.check_ram_range(self.i as usize, regs.len())?;
+        for (offset, r) in regs.into_iter().enumerate() {
+            self.reg[r] = self.ram[self.i as usize + offset];
+        }
+        self.pc = self.pc.wrapping_add(2);
+        Ok(())
+    }
+
+    fn save_flags_up_to(&mut self, x: u8) {
+        let count = 1 + x as usize;
+        self.rpl[0..count].copy_from_slice(&self.reg[0..count]);
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    fn load_flags_up_to(&mut self, x: u8) {
+        let count = 1 + x as usize;
+        self.reg[0..count].copy_from_slice(&self.rpl[0..count]);
+        self.pc = self.pc.wrapping_add(2);
+    }
+
+    // Value of I after Fx55/Fx65 have accessed registers V0 through Vx
+    fn i_after_load_store(&self, x: u8) -> u16 {
+        match self.quirks.load_store {
+            LoadStoreQuirk::Unchanged => self.i,
+            LoadStoreQuirk::IncrementByX => self.i.wrapping_add(x as u16),
+            LoadStoreQuirk::IncrementByXPlusOne => self.i.wrapping_add(x as u16 + 1),
+        }
     }
 }
 
@@ -544,14 +836,6 @@ impl Chip8Instruction {
     }
 }
 
-fn u16_from_nibbles(n0: u8, n1: u8, n2: u8, n3: u8) -> u16 {
-    (((n0 as u16) << 12) | ((n1 as u16) << 8) | ((n2 as u16) << 4) | n3 as u16)
-}
-
-fn u8_from_nibbles(n0: u8, n1: u8) -> u8 {
-    (n0 << 4) | n1
-}
-
 const FONT_SPRITES: [u8; 80] = [
     // "0"	Binary	Hex
     0xF0,    0x90,    0x90,    0x90,    0xF0,
@@ -586,3 +870,40 @@ const FONT_SPRITES: [u8; 80] = [
     // "F"	Binary	Hex
     0xF0,    0x80,    0xF0,    0x80,    0x80,
 ];
+
+// SUPER-CHIP 8x10 font sprites, loaded right after the regular ones
+const BIG_FONT_ADDR: usize = 0x50;
+const BIG_FONT_SPRITES: [u8; 160] = [
+    // "0"
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF,
+    // "1"
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF,
+    // "2"
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    // "3"
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    // "4"
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03,
+    // "5"
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    // "6"
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    // "7"
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18,
+    // "8"
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF,
+    // "9"
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF,
+    // "A"
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3,
+    // "B"
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC,
+    // "C"
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C,
+    // "D"
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC,
+    // "E"
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF,
+    // "F"
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0,
+];
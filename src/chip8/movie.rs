@@ -0,0 +1,266 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use super::{BuiltinRandom, Quirks};
+
+// Movies start with these bytes, followed by the format version
+const MAGIC: &[u8; 4] = b"C8MV";
+const VERSION: u16 = 4;
+
+// A key pressed or released at the start of a frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct MovieEvent {
+    pub frame: u32,
+    pub key: u8,
+    pub pressed: bool,
+}
+
+// Recording of the keyboard input of a run, along with everything else needed to replay it
+// exactly: the same program, run with the same settings, gets the same keys on the same frames
+#[derive(Clone, Debug, PartialEq)]
+pub struct Movie {
+    pub rom_hash: u64,
+    pub clock_speed: u32,
+    pub seed: u64,
+    pub rng: &'static str,          // One of BuiltinRandom::NAMES
+    pub quirks: Quirks,
+    pub xo_chip: bool,
+    pub frames: u32,                // Length of the run
+    pub events: Vec<MovieEvent>,    // In frame order
+}
+
+// Position in the events of a movie being replayed, so each frame only looks at its own
+pub struct Playback<'a> {
+    events: &'a [MovieEvent],
+    next: usize,                    // First event not replayed yet
+}
+
+impl<'a> Playback<'a> {
+
+    // Key events at the start of 'frame', which must not be before the previous call's
+    pub fn events_at(&mut self, frame: u32) -> &'a [MovieEvent] {
+        let pending = &self.events[self.next..];
+        let start = pending.iter().take_while(|e| e.frame < frame).count();
+        let end = start + pending[start..].iter().take_while(|e| e.frame == frame).count();
+        self.next += end;
+        &pending[start..end]
+    }
+}
+
+// Errors loading a movie
+#[derive(Debug)]
+pub enum MovieError {
+    // The data couldn't be read
+    Io(io::Error),
+    // The data doesn't start like a movie
+    NotAMovie,
+    // The movie was written in a format version this build doesn't know
+    UnsupportedVersion(u16),
+    // The movie is truncated, or holds impossible values
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for MovieError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            MovieError::Io(ref e) => write!(f, "couldn't read movie: {}", e),
+            MovieError::NotAMovie => write!(f, "not a movie"),
+            MovieError::UnsupportedVersion(v) =>
+                write!(f, "unsupported movie version {} (expected {})", v, VERSION),
+            MovieError::Corrupt(what) => write!(f, "corrupt movie: {}", what),
+        }
+    }
+}
+
+impl Error for MovieError {
+    fn description(&self) -> &str {
+        match *self {
+            MovieError::Io(_) => "i/o error",
+            MovieError::NotAMovie => "not a movie",
+            MovieError::UnsupportedVersion(_) => "unsupported movie version",
+            MovieError::Corrupt(_) => "corrupt movie",
+        }
+    }
+}
+
+// Running out of data while reading means the movie is truncated
+fn truncated(e: io::Error) -> MovieError {
+    match e.kind() {
+        io::ErrorKind::UnexpectedEof => MovieError::Corrupt("truncated data"),
+        _ => MovieError::Io(e),
+    }
+}
+
+// FNV-1a hash of a ROM, to tell whether a movie was recorded with it
+fn rom_hash(rom: &[u8]) -> u64 {
+    rom.iter().fold(0xCBF29CE484222325, |hash, &b| (hash ^ b as u64).wrapping_mul(0x100000001B3))
+}
+
+impl Movie {
+
+    // Create an empty movie for a run of 'rom' with the given settings
+    pub fn new(rom: &[u8], clock_speed: u32, seed: u64, rng: &'static str, quirks: Quirks, xo_chip: bool) -> Movie {
+        Movie {
+            rom_hash: rom_hash(rom),
+            clock_speed,
+            seed,
+            rng,
+            quirks,
+            xo_chip,
+            frames: 0,
+            events: Vec::new(),
+        }
+    }
+
+    // Add a key event at 'frame', which must not be before the previous event's
+    pub fn record(&mut self, frame: u32, key: u8, pressed: bool) {
+        debug_assert!(self.events.last().is_none_or(|e| e.frame <= frame));
+        self.events.push(MovieEvent { frame, key, pressed });
+        self.frames = ::std::cmp::max(self.frames, frame + 1);
+    }
+
+    // Whether the movie was recorded running 'rom'
+    pub fn matches_rom(&self, rom: &[u8]) -> bool {
+        self.rom_hash == rom_hash(rom)
+    }
+
+    // Replay the events frame by frame, from the first one
+    pub fn playback(&self) -> Playback<'_> {
+        Playback { events: &self.events, next: 0 }
+    }
+
+    // Random number generator the run started with
+    pub fn random_source(&self) -> BuiltinRandom {
+        // it was checked to be a known name when created or loaded
+        BuiltinRandom::from_name(self.rng, self.seed).unwrap()
+    }
+
+    // Write the movie: magic, version, header and events
+    pub fn save(&self, mut w: impl Write) -> io::Result<()> {
+        w.write_all(MAGIC)?;
+        w.write_u16::<BigEndian>(VERSION)?;
+        w.write_u64::<BigEndian>(self.rom_hash)?;
+        w.write_u32::<BigEndian>(self.clock_speed)?;
+        w.write_u64::<BigEndian>(self.seed)?;
+        let rng = BuiltinRandom::NAMES.iter().position(|&name| name == self.rng).unwrap();
+        w.write_u8(rng as u8)?;
+        w.write_all(&self.quirks.to_bytes())?;
+        w.write_u8(self.xo_chip as u8)?;
+        w.write_u32::<BigEndian>(self.frames)?;
+        w.write_u32::<BigEndian>(self.events.len() as u32)?;
+        for event in &self.events {
+            w.write_u32::<BigEndian>(event.frame)?;
+            w.write_u8(event.key | (event.pressed as u8) << 7)?;
+        }
+        w.flush()
+    }
+
+    // Read a movie written by 'save'
+    pub fn load(mut r: impl Read) -> Result<Movie, MovieError> {
+        let mut magic = [0; 4];
+        if let Err(e) = r.read_exact(&mut magic) {
+            return Err(match e.kind() {
+                io::ErrorKind::UnexpectedEof => MovieError::NotAMovie,
+                _ => MovieError::Io(e),
+            });
+        }
+        if &magic != MAGIC {
+            return Err(MovieError::NotAMovie);
+        }
+        let version = r.read_u16::<BigEndian>().map_err(truncated)?;
+        if version != VERSION {
+            return Err(MovieError::UnsupportedVersion(version));
+        }
+        let rom_hash = r.read_u64::<BigEndian>().map_err(truncated)?;
+        let clock_speed = r.read_u32::<BigEndian>().map_err(truncated)?;
+        let seed = r.read_u64::<BigEndian>().map_err(truncated)?;
+        let rng = *BuiltinRandom::NAMES.get(r.read_u8().map_err(truncated)? as usize)
+            .ok_or(MovieError::Corrupt("unknown random number generator"))?;
+        let mut quirks = [0; 8];
+        r.read_exact(&mut quirks).map_err(truncated)?;
+        let quirks = Quirks::from_bytes(&quirks).ok_or(MovieError::Corrupt("invalid quirk"))?;
+        let xo_chip = match r.read_u8().map_err(truncated)? {
+            0 => false,
+            1 => true,
+            _ => return Err(MovieError::Corrupt("invalid boolean")),
+        };
+        let frames = r.read_u32::<BigEndian>().map_err(truncated)?;
+        let count = r.read_u32::<BigEndian>().map_err(truncated)?;
+        let mut events = Vec::new();
+        for _ in 0..count {
+            let frame = r.read_u32::<BigEndian>().map_err(truncated)?;
+            let key = r.read_u8().map_err(truncated)?;
+            if key & 0x70 != 0 || events.last().is_some_and(|e: &MovieEvent| e.frame > frame) || frame >= frames {
+                return Err(MovieError::Corrupt("invalid event"));
+            }
+            events.push(MovieEvent { frame, key: key & 0x0F, pressed: key & 0x80 != 0 });
+        }
+        Ok(Movie {
+            rom_hash,
+            clock_speed,
+            seed,
+            rng,
+            quirks,
+            xo_chip,
+            frames,
+            events,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn movie() -> Movie {
+        let mut movie = Movie::new(&[0x12, 0x00], 500, 42, "xorshift", Quirks::COSMAC_VIP, false);
+        movie.record(3, 0xA, true);
+        movie.record(3, 0x1, true);
+        movie.record(10, 0xA, false);
+        movie.frames = 20;
+        movie
+    }
+
+    #[test]
+    fn save_and_load() {
+        let mut data = Vec::new();
+        movie().save(&mut data).unwrap();
+        let loaded = Movie::load(&data[..]).unwrap();
+        assert_eq!(loaded, movie());
+        assert!(loaded.matches_rom(&[0x12, 0x00]));
+        assert!(!loaded.matches_rom(&[0x12, 0x02]));
+    }
+
+    #[test]
+    fn events_at_frame() {
+        let movie = movie();
+        let mut playback = movie.playback();
+        assert!(playback.events_at(0).is_empty());
+        let keys: Vec<u8> = playback.events_at(3).iter().map(|e| e.key).collect();
+        assert_eq!(keys, vec![0xA, 0x1]);
+        assert!(playback.events_at(4).is_empty());
+        assert_eq!(playback.events_at(10), &[MovieEvent { frame: 10, key: 0xA, pressed: false }]);
+        assert!(playback.events_at(11).is_empty());
+    }
+
+    #[test]
+    fn rejects_bad_data() {
+        let mut data = Vec::new();
+        movie().save(&mut data).unwrap();
+        match Movie::load(&data[..data.len() - 1]) {
+            Err(MovieError::Corrupt(_)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+        data[5] = 9;
+        match Movie::load(&data[..]) {
+            Err(MovieError::UnsupportedVersion(9)) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+        match Movie::load(&b"C8SN"[..]) {
+            Err(MovieError::NotAMovie) => {},
+            other => panic!("unexpected result: {:?}", other),
+        }
+    }
+}
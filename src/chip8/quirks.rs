@@ -0,0 +1,163 @@
+// How Fx55/Fx65 leave the I register after copying registers from/to memory
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LoadStoreQuirk {
+    // I is left unchanged
+    Unchanged,
+    // I is incremented by X (CHIP-48)
+    IncrementByX,
+    // I is left pointing right after the last accessed address (COSMAC VIP)
+    IncrementByXPlusOne,
+}
+
+// Interpretation chosen for each of the opcodes whose behaviour differs
+// among CHIP-8 implementations
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Quirks {
+    // 8xy6/8xyE shift Vy and store the result in Vx, instead of shifting Vx in place
+    pub shift_uses_vy: bool,
+    // Fx55/Fx65 effect on I
+    pub load_store: LoadStoreQuirk,
+    // Bxnn jumps to xnn + Vx, instead of Bnnn jumping to nnn + V0
+    pub jump_uses_vx: bool,
+    // 8xy1/8xy2/8xy3 reset VF to 0
+    pub logic_resets_vf: bool,
+    // Fx1E sets VF to 1 when I goes past 0xFFF, and to 0 otherwise
+    pub i_overflow_sets_vf: bool,
+    // Dxyn draws the parts of a sprite past the edges of the screen on the opposite
+    // edge, instead of clipping them. Sprites start at wrapped coordinates either way
+    pub wrap_sprites: bool,
+    // Dxyn waits for the next frame (the COSMAC VIP's vertical blank interrupt) before
+    // going on, so at most one sprite is drawn per frame
+    pub display_wait: bool,
+    // Fx0A waits for a key to be pressed and then released, instead of going on as
+    // soon as it is pressed
+    pub key_wait_release: bool,
+}
+
+impl Quirks {
+    // The original interpreter on the RCA COSMAC VIP
+    pub const COSMAC_VIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store: LoadStoreQuirk::IncrementByXPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: true,
+        i_overflow_sets_vf: false,
+        wrap_sprites: false,
+        display_wait: true,
+        key_wait_release: true,
+    };
+
+    // CHIP-48, for the HP-48 calculators
+    pub const CHIP_48: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store: LoadStoreQuirk::IncrementByX,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        i_overflow_sets_vf: false,
+        wrap_sprites: false,
+        display_wait: false,
+        key_wait_release: false,
+    };
+
+    // SUPER-CHIP 1.1, also for the HP-48 calculators
+    pub const SUPER_CHIP: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store: LoadStoreQuirk::Unchanged,
+        jump_uses_vx: true,
+        logic_resets_vf: false,
+        i_overflow_sets_vf: false,
+        wrap_sprites: false,
+        display_wait: false,
+        key_wait_release: false,
+    };
+
+    // XO-CHIP, as defined by Octo
+    pub const XO_CHIP: Quirks = Quirks {
+        shift_uses_vy: true,
+        load_store: LoadStoreQuirk::IncrementByXPlusOne,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        i_overflow_sets_vf: false,
+        wrap_sprites: true,
+        display_wait: false,
+        key_wait_release: true,
+    };
+
+    // What most modern interpreters (and most technical references) do
+    pub const MODERN: Quirks = Quirks {
+        shift_uses_vy: false,
+        load_store: LoadStoreQuirk::Unchanged,
+        jump_uses_vx: false,
+        logic_resets_vf: false,
+        i_overflow_sets_vf: false,
+        wrap_sprites: false,
+        display_wait: false,
+        key_wait_release: false,
+    };
+
+    // Names accepted by 'from_name'
+    pub const PRESET_NAMES: &'static [&'static str] = &["vip", "chip48", "schip", "xochip", "modern"];
+
+    // Get a preset by its name
+    pub fn from_name(name: &str) -> Option<Quirks> {
+        match name {
+            "vip" => Some(Quirks::COSMAC_VIP),
+            "chip48" => Some(Quirks::CHIP_48),
+            "schip" => Some(Quirks::SUPER_CHIP),
+            "xochip" => Some(Quirks::XO_CHIP),
+            "modern" => Some(Quirks::MODERN),
+            _ => None,
+        }
+    }
+
+    // Quirks as bytes, as stored in snapshots and movies
+    pub(super) fn to_bytes(self) -> [u8; 8] {
+        [
+            self.shift_uses_vy as u8,
+            match self.load_store {
+                LoadStoreQuirk::Unchanged => 0,
+                LoadStoreQuirk::IncrementByX => 1,
+                LoadStoreQuirk::IncrementByXPlusOne => 2,
+            },
+            self.jump_uses_vx as u8,
+            self.logic_resets_vf as u8,
+            self.i_overflow_sets_vf as u8,
+            self.wrap_sprites as u8,
+            self.display_wait as u8,
+            self.key_wait_release as u8,
+        ]
+    }
+
+    // Quirks from 'to_bytes' bytes, None if they aren't valid
+    pub(super) fn from_bytes(bytes: &[u8]) -> Option<Quirks> {
+        let flag = |b: u8| match b {
+            0 => Some(false),
+            1 => Some(true),
+            _ => None,
+        };
+        match bytes {
+            &[shift, load_store, jump, logic, i_overflow, wrap, display_wait, key_wait_release] => Some(Quirks {
+                shift_uses_vy: flag(shift)?,
+                load_store: match load_store {
+                    0 => LoadStoreQuirk::Unchanged,
+                    1 => LoadStoreQuirk::IncrementByX,
+                    2 => LoadStoreQuirk::IncrementByXPlusOne,
+                    _ => return None,
+                },
+                jump_uses_vx: flag(jump)?,
+                logic_resets_vf: flag(logic)?,
+                i_overflow_sets_vf: flag(i_overflow)?,
+                wrap_sprites: flag(wrap)?,
+                display_wait: flag(display_wait)?,
+                key_wait_release: flag(key_wait_release)?,
+            }),
+            _ => None,
+        }
+    }
+}
+
+impl Default for Quirks {
+    fn default() -> Quirks {
+        Quirks::MODERN
+    }
+}
@@ -0,0 +1,214 @@
+use std::collections::VecDeque;
+use std::marker::PhantomData;
+use super::{Chip8State, RandomSource};
+
+// Identical runs shorter than this don't split a changed run, as each run has its own overhead
+const MIN_GAP: usize = 8;
+
+// Bytes to write back at 'offset' to undo a change
+struct Patch {
+    offset: usize,
+    bytes: Vec<u8>,
+}
+
+// Bounded history of machine states, for stepping back in time. Only the latest state is
+// kept whole: each older one is stored as the changes needed to get it back from the next,
+// which are usually small since most of the RAM stays the same. The states drawn random
+// numbers from 'R', whose generator state is stored along
+pub struct Rewind<R> {
+    capacity: usize,
+    latest: Option<Vec<u8>>,            // Latest state, serialized as in snapshots
+    deltas: VecDeque<Vec<Patch>>,       // Changes back to each previous state, newest first
+    random: PhantomData<R>,
+}
+
+impl<R: RandomSource> Rewind<R> {
+
+    // Create a history holding up to 'capacity' states besides the latest one
+    pub fn new(capacity: usize) -> Rewind<R> {
+        Rewind { capacity, latest: None, deltas: VecDeque::new(), random: PhantomData }
+    }
+
+    // Number of states it is possible to step back
+    pub fn len(&self) -> usize {
+        self.deltas.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.latest.is_none()
+    }
+
+    pub fn clear(&mut self) {
+        self.latest = None;
+        self.deltas.clear();
+    }
+
+    // Bytes used by the stored changes, without the latest state
+    pub fn delta_size(&self) -> usize {
+        self.deltas.iter().flat_map(|d| d.iter()).map(|p| p.bytes.len() + 16).sum()
+    }
+
+    // Add a state to the history, forgetting the oldest one if full
+    pub fn push(&mut self, state: &Chip8State<R>) {
+        let current = state.snapshot_payload();
+        match self.latest.take() {
+            // states with different amounts of RAM can't be diffed, so the history restarts
+            Some(ref previous) if previous.len() != current.len() => self.deltas.clear(),
+            Some(previous) => self.deltas.push_front(diff(&current, &previous)),
+            None => {}
+        }
+        if self.deltas.len() > self.capacity {
+            self.deltas.pop_back();
+        }
+        self.latest = Some(current);
+    }
+
+    // Go back 'n' states (or as many as stored), which become the latest state. Returns
+    // it, or None if the history is empty
+    pub fn step_back(&mut self, n: usize) -> Option<Chip8State<R>> {
+        {
+            let latest = self.latest.as_mut()?;
+            for _ in 0..n {
+                match self.deltas.pop_front() {
+                    Some(delta) => {
+                        for patch in delta {
+                            latest[patch.offset..patch.offset + patch.bytes.len()].copy_from_slice(&patch.bytes);
+                        }
+                    },
+                    None => break,
+                }
+            }
+        }
+        self.latest.as_ref().map(|latest| {
+            // it was serialized from a valid state, with the same kind of generator
+            Chip8State::from_snapshot_payload(latest).unwrap()
+        })
+    }
+}
+
+// Changes to turn 'from' into 'to', both of the same size
+fn diff(from: &[u8], to: &[u8]) -> Vec<Patch> {
+    let mut patches = Vec::new();
+    let mut offset = 0;
+    while offset < from.len() {
+        if from[offset] == to[offset] {
+            offset += 1;
+            continue;
+        }
+        // extend the run until finding enough identical bytes
+        let start = offset;
+        let mut end = offset + 1;
+        let mut same = 0;
+        while end < from.len() && same < MIN_GAP {
+            if from[end] == to[end] {
+                same += 1;
+            } else {
+                same = 0;
+            }
+            end += 1;
+        }
+        end -= same;
+        patches.push(Patch { offset: start, bytes: to[start..end].to_vec() });
+        offset = end;
+    }
+    patches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{BuiltinRandom, XorShift};
+
+    // A machine counting in V0 and storing the count in RAM
+    fn counter() -> Chip8State {
+        // 200: V0 += 1; 202: I := 300; 204: store V0; 206: jump 200
+        Chip8State::new().load(&[0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00])
+    }
+
+    #[test]
+    fn step_back_instructions() {
+        let mut rewind = Rewind::new(100);
+        let mut state = counter();
+        rewind.push(&state);
+        for _ in 0..40 {
+            state = state.exec_instruction().unwrap();
+            rewind.push(&state);
+        }
+        assert_eq!(rewind.len(), 40);
+        assert_eq!(state.reg[0], 10);
+        let back: Chip8State = rewind.step_back(8).unwrap();
+        assert_eq!(back.reg[0], 8);
+        assert_eq!(back.ram[0x300], 8);
+        assert_eq!(back.pc, state.pc);
+        assert_eq!(rewind.len(), 32);
+        // history goes on from there
+        let state = back.exec_instruction().unwrap();
+        rewind.push(&state);
+        let back: Chip8State = rewind.step_back(1).unwrap();
+        assert_eq!(back.reg[0], 8);
+    }
+
+    #[test]
+    fn bounded_capacity() {
+        let mut rewind = Rewind::new(5);
+        let mut state = counter();
+        rewind.push(&state);
+        for _ in 0..20 {
+            state = state.exec_instruction().unwrap();
+            rewind.push(&state);
+        }
+        assert_eq!(rewind.len(), 5);
+        let oldest: Chip8State = rewind.step_back(100).unwrap();
+        assert_eq!(oldest.reg[0], 4);
+        assert_eq!(oldest.pc, 0x206);
+    }
+
+    #[test]
+    fn deltas_are_small() {
+        let mut rewind = Rewind::new(100);
+        let mut state = counter();
+        rewind.push(&state);
+        for _ in 0..100 {
+            state = state.exec_instruction().unwrap();
+            rewind.push(&state);
+        }
+        assert!(rewind.delta_size() < 100 * 64);
+    }
+
+    #[test]
+    fn empty() {
+        let mut rewind: Rewind<BuiltinRandom> = Rewind::new(10);
+        assert!(rewind.is_empty());
+        assert!(rewind.step_back(1).is_none());
+    }
+
+    #[test]
+    fn mode_change_restarts_history() {
+        let mut rewind = Rewind::new(10);
+        let state = counter();
+        rewind.push(&state);
+        rewind.push(&state.exec_instruction().unwrap());
+        let xo_state = state.with_xo_chip(true);
+        rewind.push(&xo_state);
+        assert_eq!(rewind.len(), 0);
+        let back: Chip8State = rewind.step_back(1).unwrap();
+        assert!(back.xo_chip);
+        assert_eq!(back.ram.len(), 0x10000);
+    }
+
+    #[test]
+    fn other_random_sources() {
+        // 200: V0 := random; 202: jump 200
+        let mut state = Chip8State::with_random(XorShift::new(7)).load(&[0xC0, 0xFF, 0x12, 0x00]);
+        let mut rewind = Rewind::new(10);
+        rewind.push(&state);
+        state.step().unwrap();
+        let drawn = state.reg[0];
+        rewind.push(&state);
+        state.step().unwrap();
+        rewind.push(&state);
+        let mut back = rewind.step_back(2).unwrap();
+        back.step().unwrap();
+        assert_eq!(back.reg[0], drawn);
+    }
+}
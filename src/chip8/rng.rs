@@ -0,0 +1,128 @@
+// Source of the random bytes used by Cxkk. The machine holds its own, so runs can be
+// reproduced from a seed, and its state is saved along with the rest of the machine
+pub trait RandomSource: Copy {
+    // Next random byte
+    fn next_byte(&mut self) -> u8;
+    // Internal state as bytes, to be stored in snapshots
+    fn save(&self) -> Vec<u8>;
+    // Rebuild a generator from 'save' bytes, None if they aren't valid
+    fn restore(state: &[u8]) -> Option<Self>;
+}
+
+// Xorshift64* generator, good enough for games and cheap to step
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    pub fn new(seed: u64) -> XorShift {
+        // the all-zero state would only produce zeros
+        XorShift { state: if seed == 0 { 0x9E3779B97F4A7C15 } else { seed } }
+    }
+}
+
+impl RandomSource for XorShift {
+    fn next_byte(&mut self) -> u8 {
+        self.state ^= self.state >> 12;
+        self.state ^= self.state << 25;
+        self.state ^= self.state >> 27;
+        (self.state.wrapping_mul(0x2545F4914F6CDD1D) >> 56) as u8
+    }
+
+    fn save(&self) -> Vec<u8> {
+        (0..8).rev().map(|i| (self.state >> (i * 8)) as u8).collect()
+    }
+
+    fn restore(state: &[u8]) -> Option<XorShift> {
+        if state.len() != 8 {
+            return None;
+        }
+        let value = state.iter().fold(0u64, |value, &b| value << 8 | b as u64);
+        if value == 0 { None } else { Some(XorShift { state: value }) }
+    }
+}
+
+// The generators this crate provides, the default for a machine
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BuiltinRandom {
+    XorShift(XorShift),
+}
+
+impl BuiltinRandom {
+    pub fn xorshift(seed: u64) -> BuiltinRandom {
+        BuiltinRandom::XorShift(XorShift::new(seed))
+    }
+
+    // Names accepted by 'from_name'
+    pub const NAMES: &'static [&'static str] = &["xorshift"];
+
+    // Get a generator by its name
+    pub fn from_name(name: &str, seed: u64) -> Option<BuiltinRandom> {
+        match name {
+            "xorshift" => Some(BuiltinRandom::xorshift(seed)),
+            _ => None,
+        }
+    }
+
+    // Xorshift generator with an unpredictable seed
+    pub fn from_entropy() -> BuiltinRandom {
+        BuiltinRandom::xorshift(::rand::random::<u64>())
+    }
+}
+
+impl RandomSource for BuiltinRandom {
+    fn next_byte(&mut self) -> u8 {
+        match *self {
+            BuiltinRandom::XorShift(ref mut rng) => rng.next_byte(),
+        }
+    }
+
+    // The generator's state, after a byte telling which one it is
+    fn save(&self) -> Vec<u8> {
+        let (kind, mut state) = match *self {
+            BuiltinRandom::XorShift(ref rng) => (0, rng.save()),
+        };
+        state.insert(0, kind);
+        state
+    }
+
+    fn restore(state: &[u8]) -> Option<BuiltinRandom> {
+        match state.split_first() {
+            Some((&0, rest)) => XorShift::restore(rest).map(BuiltinRandom::XorShift),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bytes<R: RandomSource>(mut rng: R, n: usize) -> Vec<u8> {
+        (0..n).map(|_| rng.next_byte()).collect()
+    }
+
+    #[test]
+    fn same_seed_same_bytes() {
+        assert_eq!(bytes(BuiltinRandom::xorshift(42), 32), bytes(BuiltinRandom::xorshift(42), 32));
+        assert_ne!(bytes(BuiltinRandom::xorshift(42), 32), bytes(BuiltinRandom::xorshift(43), 32));
+    }
+
+    #[test]
+    fn zero_seed() {
+        let values = bytes(XorShift::new(0), 16);
+        assert!(values.iter().any(|&b| b != 0));
+    }
+
+    #[test]
+    fn save_and_restore() {
+        let mut rng = BuiltinRandom::xorshift(1234);
+        rng.next_byte();
+        let restored = BuiltinRandom::restore(&rng.save()).unwrap();
+        assert_eq!(bytes(restored, 16), bytes(rng, 16));
+        assert!(BuiltinRandom::restore(&[]).is_none());
+        assert!(BuiltinRandom::restore(&[2, 0, 0]).is_none());
+        assert!(BuiltinRandom::restore(&[0, 0, 0, 0, 0, 0, 0, 0]).is_none());
+    }
+}
@@ -0,0 +1,120 @@
+use std::time::{Duration, Instant};
+
+// Frames later than this are given up on, instead of being run in a burst to catch up
+const MAX_LAG_FRAMES: u64 = 5;
+
+// Keeps frames at a steady real-time rate. Deadlines are computed from the start time
+// rather than added up frame after frame, so neither rounding nor oversleeping pile up
+pub struct FrameScheduler {
+    rate: u64,          // Frames per second
+    start: Instant,     // When frame 0 was due
+    frame: u64,         // Next frame to run, counted from 'start'
+}
+
+impl FrameScheduler {
+
+    pub fn new(rate: u32, start: Instant) -> FrameScheduler {
+        FrameScheduler { rate: rate as u64, start, frame: 0 }
+    }
+
+    // When the next frame is due
+    pub fn deadline(&self) -> Instant {
+        self.start + Duration::from_nanos(self.frame * 1_000_000_000 / self.rate)
+    }
+
+    // Time to wait at 'now' until the next frame is due
+    pub fn wait_time(&self, now: Instant) -> Duration {
+        self.deadline().saturating_duration_since(now)
+    }
+
+    // Account for a frame run at 'now'. If it ran too late, the schedule restarts from now,
+    // dropping the frames that were missed. If frames ran ahead of time (not waiting for
+    // them while fast-forwarding), it restarts too, so the next one is due a frame from now
+    pub fn frame_done(&mut self, now: Instant) {
+        let early = now < self.deadline();
+        self.frame += 1;
+        let behind = now.saturating_duration_since(self.deadline());
+        if behind > Duration::from_nanos(MAX_LAG_FRAMES * 1_000_000_000 / self.rate) {
+            self.start = now;
+            self.frame = 0;
+        } else if early {
+            self.start = now;
+            self.frame = 1;
+        }
+    }
+}
+
+// Instructions to run in 'frame' for 'speed' instructions per second at 'rate' frames per
+// second. When they don't divide evenly the remainder is spread over the frames, so every
+// second runs exactly 'speed' instructions
+pub fn instructions_in_frame(speed: u32, rate: u32, frame: u64) -> u64 {
+    let (speed, rate) = (speed as u64, rate as u64);
+    (frame + 1) * speed / rate - frame * speed / rate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn instructions_spread_over_frames() {
+        let second = |speed| (0..60).map(|f| instructions_in_frame(speed, 60, f)).collect::<Vec<_>>();
+        assert!(second(600).iter().all(|&n| n == 10));
+        // 500Hz: 8 or 9 instructions per frame, not a rounded 8 (480Hz)
+        assert!(second(500).iter().all(|&n| n == 8 || n == 9));
+        assert_eq!(second(500).iter().sum::<u64>(), 500);
+        assert_eq!(second(30).iter().sum::<u64>(), 30);
+        assert_eq!(instructions_in_frame(500, 60, 60), instructions_in_frame(500, 60, 0));
+    }
+
+    #[test]
+    fn deadlines_do_not_drift() {
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new(60, start);
+        for _ in 0..60 {
+            let deadline = scheduler.deadline();
+            scheduler.frame_done(deadline);
+        }
+        // 60 frames of 16.67ms, not of a rounded 16ms
+        assert_eq!(scheduler.deadline() - start, Duration::from_secs(1));
+    }
+
+    #[test]
+    fn late_frames_are_made_up() {
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new(60, start);
+        scheduler.frame_done(start);
+        // the second frame overslept by 10ms, so the third one is due sooner
+        let late = start + Duration::from_millis(26);
+        scheduler.frame_done(late);
+        assert_eq!(scheduler.wait_time(late), Duration::from_nanos(7_333_333));
+    }
+
+    #[test]
+    fn long_stalls_are_dropped() {
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new(60, start);
+        let stalled = start + Duration::from_secs(2);
+        scheduler.frame_done(stalled);
+        assert_eq!(scheduler.deadline(), stalled);
+        scheduler.frame_done(stalled);
+        assert_eq!(scheduler.wait_time(stalled), Duration::from_nanos(16_666_666));
+    }
+
+    #[test]
+    fn fast_forward_doesnt_delay_later_frames() {
+        let start = Instant::now();
+        let mut scheduler = FrameScheduler::new(60, start);
+        // a second unthrottled, at 600 frames per second
+        let mut now = start;
+        for _ in 0..600 {
+            now += Duration::from_nanos(1_666_666);
+            scheduler.frame_done(now);
+        }
+        // throttled again, the next frame is due a frame from the last one
+        assert_eq!(scheduler.wait_time(now), Duration::from_nanos(16_666_666));
+        let deadline = scheduler.deadline();
+        scheduler.frame_done(deadline);
+        assert_eq!(scheduler.wait_time(deadline), Duration::from_nanos(16_666_667));
+    }
+}
@@ -0,0 +1,195 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::io::{Read, Write};
+use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+use super::{Chip8State, Quirks, RandomSource, STACK_DEPTH};
+use super::display::{Chip8Display, PACKED_SIZE};
+
+// Snapshots start with these bytes, followed by the format version
+const MAGIC: &[u8; 4] = b"C8SN";
+const VERSION: u16 = 8;
+
+// Errors loading a snapshot
+#[derive(Debug)]
+pub enum SnapshotError {
+    // The data couldn't be read
+    Io(io::Error),
+    // The data doesn't start like a snapshot
+    NotASnapshot,
+    // The snapshot was written in a format version this build doesn't know
+    UnsupportedVersion(u16),
+    // The snapshot is truncated, or holds impossible values
+    Corrupt(&'static str),
+}
+
+impl fmt::Display for SnapshotError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            SnapshotError::Io(ref e) => write!(f, "couldn't read snapshot: {}", e),
+            SnapshotError::NotASnapshot => write!(f, "not a snapshot"),
+            SnapshotError::UnsupportedVersion(v) =>
+                write!(f, "unsupported snapshot version {} (expected {})", v, VERSION),
+            SnapshotError::Corrupt(what) => write!(f, "corrupt snapshot: {}", what),
+        }
+    }
+}
+
+impl Error for SnapshotError {
+    fn description(&self) -> &str {
+        match *self {
+            SnapshotError::Io(_) => "i/o error",
+            SnapshotError::NotASnapshot => "not a snapshot",
+            SnapshotError::UnsupportedVersion(_) => "unsupported snapshot version",
+            SnapshotError::Corrupt(_) => "corrupt snapshot",
+        }
+    }
+}
+
+// Running out of data while reading the payload means it is truncated
+fn truncated(_: io::Error) -> SnapshotError {
+    SnapshotError::Corrupt("truncated data")
+}
+
+// FNV-1a hash of the payload, to detect corruption
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811C9DC5, |hash, &b| (hash ^ b as u32).wrapping_mul(0x01000193))
+}
+
+fn push_u16(payload: &mut Vec<u8>, value: u16) {
+    payload.push((value >> 8) as u8);
+    payload.push(value as u8);
+}
+
+fn read_bool(r: &mut &[u8]) -> Result<bool, SnapshotError> {
+    match r.read_u8().map_err(truncated)? {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(SnapshotError::Corrupt("invalid boolean")),
+    }
+}
+
+impl<R: RandomSource> Chip8State<R> {
+
+    // Write a snapshot of the whole machine: magic, version, payload and its checksum
+    pub fn save_state(&self, mut w: impl Write) -> io::Result<()> {
+        let payload = self.snapshot_payload();
+        w.write_all(MAGIC)?;
+        w.write_u16::<BigEndian>(VERSION)?;
+        w.write_all(&payload)?;
+        w.write_u32::<BigEndian>(checksum(&payload))?;
+        w.flush()
+    }
+
+    // Read a snapshot written by 'save_state'
+    pub fn load_state(mut r: impl Read) -> Result<Chip8State<R>, SnapshotError> {
+        let mut data = Vec::new();
+        r.read_to_end(&mut data).map_err(SnapshotError::Io)?;
+        if data.len() < MAGIC.len() + 2 || &data[..MAGIC.len()] != MAGIC {
+            return Err(SnapshotError::NotASnapshot);
+        }
+        let version = (&data[MAGIC.len()..]).read_u16::<BigEndian>().map_err(truncated)?;
+        if version != VERSION {
+            return Err(SnapshotError::UnsupportedVersion(version));
+        }
+        let body = &data[MAGIC.len() + 2..];
+        if body.len() < 4 {
+            return Err(SnapshotError::Corrupt("truncated data"));
+        }
+        let (payload, mut sum) = body.split_at(body.len() - 4);
+        if sum.read_u32::<BigEndian>().map_err(truncated)? != checksum(payload) {
+            return Err(SnapshotError::Corrupt("checksum mismatch"));
+        }
+        Chip8State::from_snapshot_payload(payload)
+    }
+
+    // The whole state as bytes, as stored in snapshots
+    pub(super) fn snapshot_payload(&self) -> Vec<u8> {
+        let mut payload = Vec::new();
+        // the generator goes first, as the state is built around it when loading
+        let rng = self.rng.save();
+        payload.push(rng.len() as u8);
+        payload.extend_from_slice(&rng);
+        // the mode goes before the RAM, as it sets how much of it there is
+        payload.push(self.xo_chip as u8);
+        payload.extend_from_slice(&self.ram);
+        payload.extend_from_slice(&self.reg);
+        push_u16(&mut payload, self.i);
+        for &addr in self.stack.iter() {
+            push_u16(&mut payload, addr);
+        }
+        payload.push(self.sp);
+        payload.push(self.t_delay);
+        payload.push(self.t_sound);
+        push_u16(&mut payload, self.pc);
+        payload.extend_from_slice(&self.display.to_bytes());
+        payload.push(self.display.is_hires() as u8);
+        payload.push(self.display.planes());
+        let keys = (0..16).fold(0u16, |keys, k| keys | (self.keyboard.is_key_pressed(k) as u16) << k);
+        push_u16(&mut payload, keys);
+        payload.push(self.waiting_for_key.unwrap_or(0xFF));
+        payload.push(self.key_to_release.unwrap_or(0xFF));
+        payload.extend_from_slice(&self.quirks.to_bytes());
+        payload.extend_from_slice(&self.rpl);
+        payload.push(self.halted as u8);
+        payload.push(self.waiting_for_vblank as u8);
+        payload.extend_from_slice(&self.audio_pattern);
+        payload.push(self.audio_pitch);
+        payload
+    }
+
+    // Rebuild a state from 'snapshot_payload' bytes
+    pub(super) fn from_snapshot_payload(payload: &[u8]) -> Result<Chip8State<R>, SnapshotError> {
+        let mut r = payload;
+        let mut rng = vec![0; r.read_u8().map_err(truncated)? as usize];
+        r.read_exact(&mut rng).map_err(truncated)?;
+        let rng = R::restore(&rng).ok_or(SnapshotError::Corrupt("invalid random generator state"))?;
+        let xo_chip = read_bool(&mut r)?;
+        let mut state = Chip8State::with_random(rng).with_xo_chip(xo_chip);
+        r.read_exact(&mut state.ram).map_err(truncated)?;
+        r.read_exact(&mut state.reg).map_err(truncated)?;
+        state.i = r.read_u16::<BigEndian>().map_err(truncated)?;
+        for addr in state.stack.iter_mut() {
+            *addr = r.read_u16::<BigEndian>().map_err(truncated)?;
+        }
+        state.sp = r.read_u8().map_err(truncated)?;
+        if state.sp as usize > STACK_DEPTH {
+            return Err(SnapshotError::Corrupt("invalid stack pointer"));
+        }
+        state.t_delay = r.read_u8().map_err(truncated)?;
+        state.t_sound = r.read_u8().map_err(truncated)?;
+        state.pc = r.read_u16::<BigEndian>().map_err(truncated)?;
+        let mut pixels = vec![0; PACKED_SIZE];
+        r.read_exact(&mut pixels).map_err(truncated)?;
+        let hires = read_bool(&mut r)?;
+        let planes = r.read_u8().map_err(truncated)?;
+        state.display = Chip8Display::from_bytes(&pixels, hires, planes)
+            .ok_or(SnapshotError::Corrupt("invalid display"))?;
+        let keys = r.read_u16::<BigEndian>().map_err(truncated)?;
+        for k in (0..16).filter(|k| keys & (1 << k) != 0) {
+            state.keyboard.key_pressed(k);
+        }
+        state.waiting_for_key = match r.read_u8().map_err(truncated)? {
+            0xFF => None,
+            x if x < 16 => Some(x),
+            _ => return Err(SnapshotError::Corrupt("invalid register waiting for a key")),
+        };
+        state.key_to_release = match r.read_u8().map_err(truncated)? {
+            0xFF => None,
+            k if k < 16 && state.waiting_for_key.is_some() => Some(k),
+            _ => return Err(SnapshotError::Corrupt("invalid key pressed while waiting")),
+        };
+        let mut quirks = [0; 8];
+        r.read_exact(&mut quirks).map_err(truncated)?;
+        state.quirks = Quirks::from_bytes(&quirks).ok_or(SnapshotError::Corrupt("invalid quirk"))?;
+        r.read_exact(&mut state.rpl).map_err(truncated)?;
+        state.halted = read_bool(&mut r)?;
+        state.waiting_for_vblank = read_bool(&mut r)?;
+        r.read_exact(&mut state.audio_pattern).map_err(truncated)?;
+        state.audio_pitch = r.read_u8().map_err(truncated)?;
+        if !r.is_empty() {
+            return Err(SnapshotError::Corrupt("trailing data"));
+        }
+        Ok(state)
+    }
+}
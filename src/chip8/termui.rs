@@ -1,88 +1,608 @@
 use super::*;
+use super::debugger::Debugger;
 
-use std::io::{Write, stdout, stdin};
+use std::cmp;
+use std::io;
+use std::io::{BufWriter, Write, stdout, stdin};
 use termion::{color, cursor, clear};
 use termion::event::*;
 use termion::input::TermRead;
-use termion::raw::IntoRawMode;
 
-pub fn display_loop(state_rx: ::chan::Receiver<Chip8State>) {
+// Everything needed to draw a frame
+pub struct Frame {
+    pub state: Chip8State,
+    pub pixels: Pixels,                 // the screen as shown, with persistence applied
+    pub error: Option<Chip8Error>,      // error that stopped the machine, if any
+    pub debugger: Option<Debugger>,     // debugger state, if debugging
+    pub message: Option<String>,        // status message, if any
+}
+
+// How the CHIP-8 screen is shown with terminal characters
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RenderMode {
+    // A character per pixel, shaded by XO-CHIP plane. Pixels look tall, terminal cells
+    // being about twice as high as wide
+    Block,
+    // Two rows of pixels per character, with half blocks: pixels look square
+    HalfBlock,
+    // 2x4 pixels per braille character, so the hires screen fits in 64x16 characters
+    Braille,
+}
+
+impl RenderMode {
+    // Names accepted by 'from_name'
+    pub const NAMES: &'static [&'static str] = &["block", "half", "braille"];
+
+    pub fn from_name(name: &str) -> Option<RenderMode> {
+        match name {
+            "block" => Some(RenderMode::Block),
+            "half" => Some(RenderMode::HalfBlock),
+            "braille" => Some(RenderMode::Braille),
+            _ => None,
+        }
+    }
+
+    // Columns and rows of pixels in a character
+    fn cell_size(self) -> (u8, u8) {
+        match self {
+            RenderMode::Block => (1, 1),
+            RenderMode::HalfBlock => (1, 2),
+            RenderMode::Braille => (2, 4),
+        }
+    }
+
+    // Character for the pixels of a cell, its top left pixel being at 'col', 'row'. Packed
+    // modes show a pixel lit in any XO-CHIP plane, or fading out, as lit
+    fn glyph(self, pixels: &Pixels, col: u8, row: u8) -> char {
+        let lit = |dx: u8, dy: u8| pixels.get(col + dx, row + dy).1 > 0;
+        match self {
+            RenderMode::Block => match pixels.get(col, row) {
+                (_, 0) => ' ',
+                // XO-CHIP colours: first plane, second plane, both
+                (1, LIT) => '█',
+                (2, LIT) => '▒',
+                (_, LIT) => '▓',
+                _ => '░',
+            },
+            RenderMode::HalfBlock => match (lit(0, 0), lit(0, 1)) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█',
+            },
+            RenderMode::Braille => {
+                // dot bits of the braille patterns block, column by column
+                const DOTS: [[u32; 4]; 2] = [[0x01, 0x02, 0x04, 0x40], [0x08, 0x10, 0x20, 0x80]];
+                let mut dots = 0;
+                for dx in 0..2 {
+                    for dy in 0..4 {
+                        if lit(dx, dy) {
+                            dots |= DOTS[dx as usize][dy as usize];
+                        }
+                    }
+                }
+                if dots == 0 { ' ' } else { ::std::char::from_u32(0x2800 + dots).unwrap() }
+            },
+        }
+    }
+}
+
+// How pixels just turned off are shown. Sprites are erased and drawn again with XOR, so
+// a frame may end with a moving sprite missing, which makes it flicker
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Persistence {
+    // Pixels are shown as they are
+    Off,
+    // Pixels lit in the last frame are shown lit too
+    Or,
+    // Pixels fade out over a few frames, like on the phosphor of a CRT
+    Phosphor,
+}
+
+impl Persistence {
+    // Names accepted by 'from_name'
+    pub const NAMES: &'static [&'static str] = &["off", "or", "phosphor"];
+
+    pub fn from_name(name: &str) -> Option<Persistence> {
+        match name {
+            "off" => Some(Persistence::Off),
+            "or" => Some(Persistence::Or),
+            "phosphor" => Some(Persistence::Phosphor),
+            _ => None,
+        }
+    }
+}
+
+// Brightness of a lit pixel. With phosphor persistence, pixels turned off lose a level
+// per frame
+const LIT: u8 = 4;
+
+// The screen as shown: the colour of each pixel (its XO-CHIP planes, kept while it fades
+// out) and how bright it is. It is updated along with the machine, once per frame run,
+// so persistence doesn't depend on how many frames the terminal gets to draw
+#[derive(Clone, Default)]
+pub struct Pixels {
+    cols: u8,
+    rows: u8,
+    colors: Vec<u8>,
+    levels: Vec<u8>,
+    previous: Vec<u8>,      // Colours of the machine's display when last taken
+}
+
+impl Pixels {
+    pub fn new() -> Pixels {
+        Pixels::default()
+    }
+
+    fn get(&self, col: u8, row: u8) -> (u8, u8) {
+        let index = row as usize * self.cols as usize + col as usize;
+        (self.colors[index], self.levels[index])
+    }
+
+    // Take the next frame of the machine, keeping the pixels turned off as 'persistence'
+    // says. Changing resolution starts afresh
+    pub fn update(&mut self, chip8state: &Chip8State, persistence: Persistence) {
+        let (cols, rows) = chip8state.display_size();
+        if (cols, rows) != (self.cols, self.rows) {
+            let size = cols as usize * rows as usize;
+            *self = Pixels { cols, rows, colors: vec![0; size], levels: vec![0; size], previous: vec![0; size] };
+        }
+        for row in 0..rows {
+            for col in 0..cols {
+                let index = row as usize * cols as usize + col as usize;
+                let color = chip8state.get_color(col, row);
+                let (shown, level) = match persistence {
+                    _ if color != 0 => (color, LIT),
+                    Persistence::Or if self.previous[index] != 0 => (self.previous[index], LIT),
+                    Persistence::Phosphor => (self.colors[index], self.levels[index].saturating_sub(1)),
+                    _ => (0, 0),
+                };
+                self.previous[index] = color;
+                self.colors[index] = shown;
+                self.levels[index] = level;
+            }
+        }
+    }
+
+    // Take the machine's display as it is, if it changed without running a frame (stepping
+    // in the debugger, rewinding, loading a snapshot). Otherwise pixels fading out stay
+    pub fn refresh(&mut self, chip8state: &Chip8State) {
+        let (cols, rows) = chip8state.display_size();
+        let changed = (cols, rows) != (self.cols, self.rows) || (0..rows).any(|row| (0..cols).any(|col| {
+            chip8state.get_color(col, row) != self.previous[row as usize * cols as usize + col as usize]
+        }));
+        if changed {
+            self.update(chip8state, Persistence::Off);
+        }
+    }
+}
+
+// Colours of the UI. Besides the named themes, pixel and background colours can be set
+// to any 24-bit colour (for terminals supporting it)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    pixels: [Colour; 3],    // Pixels lit in the first XO-CHIP plane, in the second, in both
+    background: Colour,     // Behind the pixels
+    banner: Colour,         // Title bar and key bar ends
+    title: Colour,
+    text: Colour,           // Row labels, messages and hints
+    key_up: Colour,         // Key bar background for released keys
+    key_down: Colour,       // Key bar background for pressed keys
+    key_text: Colour,
+    error: Colour,
+}
+
+impl Theme {
+    // The terminal's own colours
+    pub const TERMINAL: Theme = Theme {
+        pixels: [Colour::Reset; 3],
+        background: Colour::Reset,
+        banner: Colour::Blue,
+        title: Colour::Green,
+        text: Colour::Gray,
+        key_up: Colour::Gray,
+        key_down: Colour::Red,
+        key_text: Colour::Reset,
+        error: Colour::Red,
+    };
+
+    // Green phosphor CRT
+    pub const GREEN: Theme = Theme {
+        pixels: [Colour::Rgb(51, 255, 102), Colour::Rgb(20, 150, 60), Colour::Rgb(170, 255, 190)],
+        background: Colour::Rgb(5, 25, 10),
+        banner: Colour::Rgb(20, 150, 60),
+        title: Colour::Rgb(51, 255, 102),
+        text: Colour::Rgb(20, 150, 60),
+        key_up: Colour::Rgb(5, 60, 20),
+        key_down: Colour::Rgb(51, 255, 102),
+        key_text: Colour::Rgb(170, 255, 190),
+        error: Colour::Rgb(255, 80, 80),
+    };
+
+    // Amber phosphor CRT
+    pub const AMBER: Theme = Theme {
+        pixels: [Colour::Rgb(255, 176, 0), Colour::Rgb(160, 100, 0), Colour::Rgb(255, 220, 140)],
+        background: Colour::Rgb(25, 15, 0),
+        banner: Colour::Rgb(160, 100, 0),
+        title: Colour::Rgb(255, 176, 0),
+        text: Colour::Rgb(160, 100, 0),
+        key_up: Colour::Rgb(70, 45, 0),
+        key_down: Colour::Rgb(255, 176, 0),
+        key_text: Colour::Rgb(255, 220, 140),
+        error: Colour::Rgb(255, 80, 80),
+    };
+
+    // Dark pixels on a greenish LCD, like early handhelds
+    pub const LCD: Theme = Theme {
+        pixels: [Colour::Rgb(15, 56, 15), Colour::Rgb(48, 98, 48), Colour::Rgb(0, 20, 0)],
+        background: Colour::Rgb(155, 188, 15),
+        banner: Colour::Rgb(48, 98, 48),
+        title: Colour::Rgb(139, 172, 15),
+        text: Colour::Rgb(139, 172, 15),
+        key_up: Colour::Rgb(48, 98, 48),
+        key_down: Colour::Rgb(139, 172, 15),
+        key_text: Colour::Rgb(15, 56, 15),
+        error: Colour::Rgb(255, 80, 80),
+    };
+
+    // Pure white on black with bright accents, for accessibility
+    pub const HIGH_CONTRAST: Theme = Theme {
+        pixels: [Colour::Rgb(255, 255, 255), Colour::Rgb(255, 255, 0), Colour::Rgb(0, 255, 255)],
+        background: Colour::Rgb(0, 0, 0),
+        banner: Colour::Rgb(255, 255, 255),
+        title: Colour::Rgb(255, 255, 0),
+        text: Colour::Rgb(255, 255, 255),
+        key_up: Colour::Rgb(0, 0, 0),
+        key_down: Colour::Rgb(255, 255, 0),
+        key_text: Colour::Rgb(255, 255, 255),
+        error: Colour::Rgb(255, 0, 0),
+    };
+
+    // Names accepted by 'from_name'
+    pub const NAMES: &'static [&'static str] = &["terminal", "green", "amber", "lcd", "contrast"];
+
+    pub fn from_name(name: &str) -> Option<Theme> {
+        match name {
+            "terminal" => Some(Theme::TERMINAL),
+            "green" => Some(Theme::GREEN),
+            "amber" => Some(Theme::AMBER),
+            "lcd" => Some(Theme::LCD),
+            "contrast" => Some(Theme::HIGH_CONTRAST),
+            _ => None,
+        }
+    }
+
+    // Same theme, drawing pixels of every plane in the given colour
+    pub fn with_foreground(&self, rgb: (u8, u8, u8)) -> Theme {
+        let mut theme = *self;
+        theme.pixels = [Colour::Rgb(rgb.0, rgb.1, rgb.2); 3];
+        theme
+    }
+
+    // Same theme, with the given colour behind the pixels
+    pub fn with_background(&self, rgb: (u8, u8, u8)) -> Theme {
+        let mut theme = *self;
+        theme.background = Colour::Rgb(rgb.0, rgb.1, rgb.2);
+        theme
+    }
+
+    // Parse a colour written as 'RRGGBB' or '#RRGGBB'
+    pub fn parse_rgb(text: &str) -> Option<(u8, u8, u8)> {
+        let hex = text.strip_prefix('#').unwrap_or(text);
+        if hex.len() != 6 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+            return None;
+        }
+        let channel = |i: usize| u8::from_str_radix(&hex[i..i + 2], 16).unwrap();
+        Some((channel(0), channel(2), channel(4)))
+    }
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme::TERMINAL
+    }
+}
+
+// Draw the frames received on stdout, which must be in raw mode
+pub fn display_loop(frame_rx: ::chan::Receiver<Frame>, mode: RenderMode, theme: Theme) {
+    let mut stdout = BufWriter::new(stdout());
+    let mut renderer = Renderer::new(mode, theme);
     loop {
         chan_select! {
-            state_rx.recv() -> st => {
-                display(st.unwrap());
+            frame_rx.recv() -> frame => {
+                renderer.render(&mut stdout, &frame.unwrap()).unwrap();
             }
         }
     }
 }
 
-fn display(chip8state: Chip8State) {
+// Colours used by the UI: the terminal's palette ones, and 24-bit ones
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Colour {
+    Reset,
+    Blue,
+    Green,
+    Gray,
+    Red,
+    Rgb(u8, u8, u8),
+}
+
+impl Colour {
+    // The colour for a pixel at brightness 'level'. Only 24-bit colours can be dimmed
+    fn dimmed(self, level: u8) -> Colour {
+        match self {
+            Colour::Rgb(r, g, b) if level > 0 => {
+                let dim = |c: u8| (c as u16 * level as u16 / LIT as u16) as u8;
+                Colour::Rgb(dim(r), dim(g), dim(b))
+            },
+            _ => self,
+        }
+    }
+
+    fn write_fg<W: Write>(self, out: &mut W) -> io::Result<()> {
+        match self {
+            Colour::Reset => write!(out, "{}", color::Fg(color::Reset)),
+            Colour::Blue => write!(out, "{}", color::Fg(color::Blue)),
+            Colour::Green => write!(out, "{}", color::Fg(color::Green)),
+            Colour::Gray => write!(out, "{}", color::Fg(color::LightBlack)),
+            Colour::Red => write!(out, "{}", color::Fg(color::Red)),
+            Colour::Rgb(r, g, b) => write!(out, "{}", color::Fg(color::Rgb(r, g, b))),
+        }
+    }
+
+    fn write_bg<W: Write>(self, out: &mut W) -> io::Result<()> {
+        match self {
+            Colour::Reset => write!(out, "{}", color::Bg(color::Reset)),
+            Colour::Blue => write!(out, "{}", color::Bg(color::Blue)),
+            Colour::Green => write!(out, "{}", color::Bg(color::Green)),
+            Colour::Gray => write!(out, "{}", color::Bg(color::LightBlack)),
+            Colour::Red => write!(out, "{}", color::Bg(color::Red)),
+            Colour::Rgb(r, g, b) => write!(out, "{}", color::Bg(color::Rgb(r, g, b))),
+        }
+    }
+}
+
+// A character on the terminal, with its colours
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Cell {
+    ch: char,
+    fg: Colour,
+    bg: Colour,
+}
 
-    let mut stdout = stdout().into_raw_mode().unwrap();
+const BLANK: Cell = Cell { ch: ' ', fg: Colour::Reset, bg: Colour::Reset };
+
+// The terminal contents for a frame. Coordinates start at 1, like the terminal's
+struct Canvas {
+    lines: Vec<Vec<Cell>>,
+}
 
-    write!(stdout, "{clear}{goto}{blue}############################## \
-                    {green}Pure CHIP-8 joy! \
-                    {blue}##############################{color_reset}",
-                    // Full screen clear.
-                    clear = clear::All,
-                    // Goto the cell.
-                    goto  = cursor::Goto(2, 2),
-                    green = color::Fg(color::Green),
-                    blue  = color::Fg(color::Blue),
-                    color_reset = color::Fg(color::Reset)).unwrap();
+impl Canvas {
+    fn new() -> Canvas {
+        Canvas { lines: Vec::new() }
+    }
 
-    // Print display
-    for row in 0..32 {
-        write!(stdout, "{goto}{gray}{row:3}{reset}   ",
-                goto = cursor::Goto(2, (row + 4) as u16),
-                gray = color::Fg(color::LightBlack),
-                reset = color::Fg(color::Reset),
-                row = row).unwrap();
-        for col in 0..64 {
-            if chip8state.get_pixel(col, row) {
-                write!(stdout, "█").unwrap();
-            } else {
-                write!(stdout, " ").unwrap();
+    // Write 'text' starting at column 'x' of line 'y'
+    fn print(&mut self, x: u16, y: u16, fg: Colour, bg: Colour, text: &str) {
+        let (x, y) = (x as usize - 1, y as usize - 1);
+        if self.lines.len() <= y {
+            self.lines.resize(y + 1, Vec::new());
+        }
+        let line = &mut self.lines[y];
+        for (i, ch) in text.chars().enumerate() {
+            if line.len() <= x + i {
+                line.resize(x + i + 1, BLANK);
             }
+            line[x + i] = Cell { ch, fg, bg };
+        }
+    }
+
+    // Write a single cell at column 'x' of line 'y'
+    fn put(&mut self, x: u16, y: u16, cell: Cell) {
+        let mut buffer = [0; 4];
+        self.print(x, y, cell.fg, cell.bg, cell.ch.encode_utf8(&mut buffer));
+    }
+
+    fn cell(&self, x: usize, y: usize) -> Cell {
+        self.lines.get(y - 1).and_then(|line| line.get(x - 1)).cloned().unwrap_or(BLANK)
+    }
+
+    fn width(&self, y: usize) -> usize {
+        self.lines.get(y - 1).map_or(0, |line| line.len())
+    }
+}
+
+// Draws frames on a terminal, remembering the last one so only the cells that changed
+// are written again
+pub struct Renderer {
+    mode: RenderMode,
+    theme: Theme,
+    last: Option<Canvas>,
+}
+
+impl Renderer {
+    pub fn new(mode: RenderMode, theme: Theme) -> Renderer {
+        Renderer { mode, theme, last: None }
+    }
+
+    // Draw 'frame' on 'out'. The first frame clears the terminal, the next ones only move
+    // the cursor to the cells that changed and write them. Colours are left reset, and the
+    // cursor below everything drawn
+    pub fn render<W: Write>(&mut self, out: &mut W, frame: &Frame) -> io::Result<()> {
+        let canvas = draw(frame, self.mode, &self.theme);
+        let empty = Canvas::new();
+        let last = match self.last {
+            Some(ref last) => last,
+            None => {
+                write!(out, "{}", clear::All)?;
+                &empty
+            }
+        };
+        let mut position = None;
+        let (mut fg, mut bg) = (Colour::Reset, Colour::Reset);
+        let height = cmp::max(canvas.lines.len(), last.lines.len());
+        for y in 1..=height {
+            for x in 1..=cmp::max(canvas.width(y), last.width(y)) {
+                let cell = canvas.cell(x, y);
+                if cell == last.cell(x, y) {
+                    continue;
+                }
+                if position != Some((x, y)) {
+                    write!(out, "{}", cursor::Goto(x as u16, y as u16))?;
+                }
+                if cell.fg != fg {
+                    cell.fg.write_fg(out)?;
+                    fg = cell.fg;
+                }
+                if cell.bg != bg {
+                    cell.bg.write_bg(out)?;
+                    bg = cell.bg;
+                }
+                write!(out, "{}", cell.ch)?;
+                position = Some((x + 1, y));
+            }
+        }
+        if position.is_some() {
+            if fg != Colour::Reset {
+                Colour::Reset.write_fg(out)?;
+            }
+            if bg != Colour::Reset {
+                Colour::Reset.write_bg(out)?;
+            }
+            write!(out, "{}", cursor::Goto(1, height as u16 + 1))?;
+        }
+        out.flush()?;
+        self.last = Some(canvas);
+        Ok(())
+    }
+}
+
+// Lay out everything in a frame, the screen showing its pixels in 'mode'
+fn draw(frame: &Frame, mode: RenderMode, theme: &Theme) -> Canvas {
+    let chip8state = &frame.state;
+    let pixels = &frame.pixels;
+    let mut canvas = Canvas::new();
+
+    canvas.print(2, 2, theme.banner, Colour::Reset, "############################## ");
+    canvas.print(33, 2, theme.title, Colour::Reset, "Pure CHIP-8 joy! ");
+    canvas.print(50, 2, theme.banner, Colour::Reset, "##############################");
+
+    // Print display, each line labelled with its first row of pixels
+    let (width, height) = mode.cell_size();
+    let (cols, rows) = chip8state.display_size();
+    let (cols, rows) = (cols / width, rows / height);
+    for line in 0..rows {
+        let row = line * height;
+        canvas.print(2, line as u16 + 4, theme.text, Colour::Reset, &format!("{:3}", row));
+        for col in 0..cols {
+            let (x, y) = (col * width, row);
+            // packed modes draw every lit pixel like the first plane's, as bright as the
+            // brightest one
+            let (color, level) = pixels.get(x, y);
+            let fg = match mode {
+                RenderMode::Block if color > 0 => theme.pixels[color as usize - 1].dimmed(level),
+                RenderMode::Block => theme.pixels[0],
+                _ => {
+                    let brightest = (0..width).flat_map(|dx| (0..height).map(move |dy| (dx, dy)))
+                        .map(|(dx, dy)| pixels.get(x + dx, y + dy).1).max().unwrap();
+                    theme.pixels[0].dimmed(brightest)
+                },
+            };
+            let cell = Cell { ch: mode.glyph(pixels, x, y), fg, bg: theme.background };
+            canvas.put(8 + col as u16, line as u16 + 4, cell);
         }
     }
 
     // Print keys states
-    write!(stdout, "{goto}{blue}###############{color_reset} ",
-                    goto = cursor::Goto(0, 37),
-                    blue = color::Fg(color::Blue),
-                    color_reset = color::Fg(color::Reset)).unwrap();
+    let keys_top = rows as u16 + 5;
+    canvas.print(1, keys_top, theme.banner, Colour::Reset, "###############");
     for key in 0..0x10 {
-        if chip8state.is_key_down(key) {
-            write!(stdout, "{color} {key:X} {reset}",
-                    color = color::Bg(color::Red),
-                    reset = color::Bg(color::Reset),
-                    key = key).unwrap();
-        } else {
-            write!(stdout, "{color} {key:X} {reset}",
-                    color = color::Bg(color::LightBlack),
-                    reset = color::Bg(color::Reset),
-                    key = key).unwrap();
-        }
+        let bg = if chip8state.is_key_down(key) { theme.key_down } else { theme.key_up };
+        canvas.print(17 + 3 * key as u16, keys_top, theme.key_text, bg, &format!(" {:X} ", key));
+    }
+    canvas.print(66, keys_top, theme.banner, Colour::Reset, "###############");
+
+    // Print status message
+    if let Some(ref message) = frame.message {
+        canvas.print(2, rows as u16 + 6, theme.text, Colour::Reset, message);
     }
-    write!(stdout, "{blue} ###############{color_reset}",
-                    blue = color::Fg(color::Blue),
-                    color_reset = color::Fg(color::Reset)).unwrap();
 
-    stdout.flush().unwrap();
+    // Print crash report
+    if let Some(e) = frame.error {
+        draw_crash(&mut canvas, chip8state, e, rows as u16 + 7, theme);
+    }
+
+    // Print debugger pane, right of the screen and of the banner and key bar, which are
+    // wider than the screen in the packed modes
+    if let Some(ref debugger) = frame.debugger {
+        let right = cmp::max(80, cols as u16 + 7);
+        draw_debugger(&mut canvas, chip8state, debugger, right + 5, theme);
+    }
+
+    canvas
 }
 
-pub fn listen_for_keys(keys_tx: ::chan::Sender<char>) {
-    let stdin = stdin();
-    for c in stdin.keys() {
-        match c.unwrap() {
-            Key::Char(c)   => keys_tx.send(c),
-            // Key::Left      => println!("<left>"),
-            // Key::Right     => println!("<right>"),
-            // Key::Up        => println!("<up>"),
-            // Key::Down      => println!("<down>"),
-            _              => {},
+// Print the error that stopped the machine, and a dump of its registers
+// starting at terminal row 'top'
+fn draw_crash(canvas: &mut Canvas, chip8state: &Chip8State, error: Chip8Error, top: u16, theme: &Theme) {
+    canvas.print(2, top, theme.error, Colour::Reset, &format!("CRASHED: {}", error));
+    for (row, regs) in chip8state.reg.chunks(8).enumerate() {
+        let line: String = regs.iter().enumerate()
+            .map(|(col, value)| format!("V{:X}={:02X}  ", 8*row + col, value))
+            .collect();
+        canvas.print(2, top + 2 + row as u16, Colour::Reset, Colour::Reset, &line);
+    }
+    canvas.print(2, top + 4, Colour::Reset, Colour::Reset,
+                 &format!("I={i:03X}  PC={pc:03X}  SP={sp:X}  DT={dt:02X}  ST={st:02X}",
+                          i = chip8state.i,
+                          pc = chip8state.pc,
+                          sp = chip8state.sp,
+                          dt = chip8state.t_delay,
+                          st = chip8state.t_sound));
+    canvas.print(2, top + 6, theme.text, Colour::Reset, "Press 'r' to reset");
+}
+
+// Print registers, call stack and code around the PC, starting at terminal column 'left'
+fn draw_debugger(canvas: &mut Canvas, chip8state: &Chip8State, debugger: &Debugger, left: u16, theme: &Theme) {
+    let status = match debugger.address_input() {
+        Some(input) => format!("Run to: {}_", input),
+        None => debugger.status(),
+    };
+    canvas.print(left, 2, theme.banner, Colour::Reset, "DEBUGGER");
+    canvas.print(left + 9, 2, Colour::Reset, Colour::Reset, &status);
+    // registers
+    for (row, regs) in chip8state.reg.chunks(4).enumerate() {
+        let line: String = regs.iter().enumerate()
+            .map(|(col, value)| format!("V{:X}={:02X} ", 4*row + col, value))
+            .collect();
+        canvas.print(left, 4 + row as u16, Colour::Reset, Colour::Reset, &line);
+    }
+    canvas.print(left, 9, Colour::Reset, Colour::Reset,
+                 &format!("I={:03X} PC={:03X} SP={:X}", chip8state.i, chip8state.pc, chip8state.sp));
+    canvas.print(left, 10, Colour::Reset, Colour::Reset,
+                 &format!("DT={:02X} ST={:02X}", chip8state.t_delay, chip8state.t_sound));
+    // call stack, innermost call first
+    let stack: String = chip8state.call_stack().iter().rev().take(5).map(|addr| format!(" {:03X}", addr)).collect();
+    canvas.print(left, 12, Colour::Reset, Colour::Reset, &format!("Stack:{}", stack));
+    // disassembly, marking the PC and breakpoints; the window stops at the end of the address space
+    let first = ::std::cmp::min(chip8state.pc.saturating_sub(8), 0xFFE0);
+    for (line, addr) in (first..first + 32).step_by(2).enumerate() {
+        let mut text = format!("{pc}{bp}{addr:03X}  ",
+                               pc = if addr == chip8state.pc { ">" } else { " " },
+                               bp = if debugger.is_breakpoint(addr) { "*" } else { " " },
+                               addr = addr);
+        if let Some(word) = chip8state.word_at(addr) {
+            text.push_str(&format!("{:04X}  {}", word, disasm::mnemonic(word, &chip8state.quirks)));
         }
+        canvas.print(left, 14 + line as u16, Colour::Reset, Colour::Reset, &text);
+    }
+    canvas.print(left, 31, theme.text, Colour::Reset, "n:step o:over l:cont p:pause t:break g:goto");
+}
+
+pub fn listen_for_keys(keys_tx: ::chan::Sender<Key>) {
+    let stdin = stdin();
+    for key in stdin.keys() {
+        keys_tx.send(key.unwrap());
     }
 }
 
@@ -91,10 +611,235 @@ mod tests {
     use super::*;
     use test::Bencher;
 
+    fn frame(state: &Chip8State) -> Frame {
+        let mut pixels = Pixels::new();
+        pixels.update(state, Persistence::Off);
+        frame_with(state, &pixels)
+    }
+
+    fn frame_with(state: &Chip8State, pixels: &Pixels) -> Frame {
+        Frame { state: state.clone(), pixels: pixels.clone(), error: None, debugger: None, message: None }
+    }
+
+    fn screen(mode: RenderMode, sprite: &[u8]) -> Vec<String> {
+        let mut state = Chip8State::new();
+        state.display.draw_sprite(0, 0, sprite, false);
+        let canvas = draw(&frame(&state), mode, &Theme::default());
+        (4..8).map(|y| (8..12).map(|x| canvas.cell(x, y).ch).collect()).collect()
+    }
+
+    fn render(renderer: &mut Renderer, frame: &Frame) -> String {
+        let mut out = Vec::new();
+        renderer.render(&mut out, frame).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn first_frame_draws_everything() {
+        let mut renderer = Renderer::new(RenderMode::Block, Theme::default());
+        let out = render(&mut renderer, &frame(&Chip8State::new()));
+        assert!(out.starts_with(&format!("{}{}{}##", clear::All, cursor::Goto(2, 2), color::Fg(color::Blue))));
+        assert!(out.contains("Pure CHIP-8 joy!"));
+        assert!(out.ends_with(&format!("{}", cursor::Goto(1, 38))));
+    }
+
+    #[test]
+    fn unchanged_frame_writes_nothing() {
+        let mut renderer = Renderer::new(RenderMode::Block, Theme::default());
+        render(&mut renderer, &frame(&Chip8State::new()));
+        assert_eq!(render(&mut renderer, &frame(&Chip8State::new())), "");
+    }
+
+    #[test]
+    fn only_changed_pixels_are_written() {
+        let mut renderer = Renderer::new(RenderMode::Block, Theme::default());
+        let mut state = Chip8State::new();
+        render(&mut renderer, &frame(&state));
+        state.display.draw_sprite(3, 1, &[0xC0], false);
+        assert_eq!(render(&mut renderer, &frame(&state)),
+                   format!("{}██{}", cursor::Goto(11, 5), cursor::Goto(1, 38)));
+        state.display.draw_sprite(4, 1, &[0x80], false);
+        assert_eq!(render(&mut renderer, &frame(&state)),
+                   format!("{} {}", cursor::Goto(12, 5), cursor::Goto(1, 38)));
+    }
+
+    #[test]
+    fn key_bar_colours() {
+        let mut renderer = Renderer::new(RenderMode::Block, Theme::default());
+        let mut state = Chip8State::new();
+        render(&mut renderer, &frame(&state));
+        state = state.key_down(0x1);
+        assert_eq!(render(&mut renderer, &frame(&state)),
+                   format!("{}{} 1 {}{}", cursor::Goto(20, 37), color::Bg(color::Red),
+                           color::Bg(color::Reset), cursor::Goto(1, 38)));
+    }
+
+    #[test]
+    fn half_block_mode() {
+        assert_eq!(screen(RenderMode::HalfBlock, &[0xA0, 0x60, 0x00, 0x80]),
+                   vec!["▀▄█ ", "▄   ", "    ", "    "]);
+    }
+
+    #[test]
+    fn braille_mode() {
+        assert_eq!(screen(RenderMode::Braille, &[0x80, 0x40, 0x20, 0x10, 0xF0]),
+                   vec!["⠑⢄  ", "⠉⠉  ", "    ", "    "]);
+    }
+
+    #[test]
+    fn packed_modes_layout() {
+        let mut state = Chip8State::new();
+        state.display.set_hires(true);
+        let canvas = draw(&frame(&state), RenderMode::Braille, &Theme::default());
+        // 64 rows of pixels in 16 lines, labelled by their first row
+        assert_eq!(canvas.cell(3, 19).ch, '6');
+        assert_eq!(canvas.cell(4, 19).ch, '0');
+        assert_eq!(canvas.lines.len(), 21);
+        assert_eq!(canvas.cell(1, 21).ch, '#');
+        let state = Chip8State::new();
+        let canvas = draw(&frame(&state), RenderMode::HalfBlock, &Theme::default());
+        assert_eq!(canvas.lines.len(), 21);
+    }
+
+    #[test]
+    fn debugger_pane_is_clear_of_everything_else() {
+        for &mode in &[RenderMode::Block, RenderMode::HalfBlock, RenderMode::Braille] {
+            for &hires in &[false, true] {
+                let mut state = Chip8State::new();
+                state.display.set_hires(hires);
+                let mut plain = frame(&state);
+                plain.error = Some(Chip8Error::StackUnderflow { addr: 0x200 });
+                plain.message = Some(String::from("Paused"));
+                let without = draw(&plain, mode, &Theme::default());
+                plain.debugger = Some(Debugger::new(&[]));
+                let with = draw(&plain, mode, &Theme::default());
+                let left = (1..=with.width(2)).find(|&x| with.cell(x, 2).ch == 'D').unwrap();
+                // the pane is drawn right of the rest, which is left as it was
+                for y in 1..=with.lines.len() {
+                    assert!(without.width(y) < left - 1, "{:?} hires={} line {}", mode, hires, y);
+                    for x in 1..left {
+                        assert_eq!(with.cell(x, y), without.cell(x, y), "{:?} hires={} at {},{}", mode, hires, x, y);
+                    }
+                }
+                assert_eq!(with.cell(left, 31).ch, 'n');
+            }
+        }
+    }
+
+    #[test]
+    fn parse_rgb() {
+        assert_eq!(Theme::parse_rgb("ffb000"), Some((255, 176, 0)));
+        assert_eq!(Theme::parse_rgb("#0F380F"), Some((15, 56, 15)));
+        assert_eq!(Theme::parse_rgb("#0F380"), None);
+        assert_eq!(Theme::parse_rgb("+0F380F"), None);
+        assert_eq!(Theme::parse_rgb("green!"), None);
+    }
+
+    #[test]
+    fn themed_pixels() {
+        let theme = Theme::GREEN.with_foreground((1, 2, 3)).with_background((4, 5, 6));
+        let mut renderer = Renderer::new(RenderMode::Block, theme);
+        let mut state = Chip8State::new();
+        let first = render(&mut renderer, &frame(&state));
+        // unlit pixels are drawn too, for the background
+        assert!(first.contains(&format!("{}{}{}{}", cursor::Goto(8, 4), color::Fg(color::Rgb(1, 2, 3)),
+                                         color::Bg(color::Rgb(4, 5, 6)), " ".repeat(64))));
+        state.display.draw_sprite(0, 0, &[0x80], false);
+        assert_eq!(render(&mut renderer, &frame(&state)),
+                   format!("{}{}{}█{}{}{}", cursor::Goto(8, 4), color::Fg(color::Rgb(1, 2, 3)),
+                           color::Bg(color::Rgb(4, 5, 6)), color::Fg(color::Reset),
+                           color::Bg(color::Reset), cursor::Goto(1, 38)));
+    }
+
+    #[test]
+    fn or_persistence() {
+        let mut state = Chip8State::new();
+        let mut pixels = Pixels::new();
+        state.display.draw_sprite(0, 0, &[0x80], false);
+        pixels.update(&state, Persistence::Or);
+        state.display.draw_sprite(0, 0, &[0x80], false);
+        pixels.update(&state, Persistence::Or);
+        assert_eq!(pixels.get(0, 0), (1, LIT));
+        pixels.update(&state, Persistence::Or);
+        assert_eq!(pixels.get(0, 0), (0, 0));
+    }
+
+    #[test]
+    fn phosphor_persistence() {
+        let mut state = Chip8State::new().with_xo_chip(true);
+        let mut pixels = Pixels::new();
+        state.display.select_planes(2);
+        state.display.draw_sprite(0, 0, &[0x80], false);
+        pixels.update(&state, Persistence::Phosphor);
+        state.display.draw_sprite(0, 0, &[0x80], false);
+        let levels: Vec<(u8, u8)> = (0..5).map(|_| {
+            pixels.update(&state, Persistence::Phosphor);
+            pixels.get(0, 0)
+        }).collect();
+        assert_eq!(levels, vec![(2, 3), (2, 2), (2, 1), (2, 0), (2, 0)]);
+        // drawing it again lights it up at once
+        state.display.draw_sprite(0, 0, &[0x80], false);
+        pixels.update(&state, Persistence::Phosphor);
+        assert_eq!(pixels.get(0, 0), (2, LIT));
+    }
+
+    #[test]
+    fn refresh_keeps_fading_pixels_until_the_display_changes() {
+        let mut state = Chip8State::new();
+        let mut pixels = Pixels::new();
+        state.display.draw_sprite(0, 0, &[0x80], false);
+        pixels.update(&state, Persistence::Phosphor);
+        state.display.draw_sprite(0, 0, &[0x80], false);
+        pixels.update(&state, Persistence::Phosphor);
+        pixels.refresh(&state);
+        assert_eq!(pixels.get(0, 0), (1, LIT - 1));
+        state.display.draw_sprite(1, 0, &[0x80], false);
+        pixels.refresh(&state);
+        assert_eq!(pixels.get(0, 0), (0, 0));
+        assert_eq!(pixels.get(1, 0), (1, LIT));
+    }
+
+    #[test]
+    fn fading_pixels_are_dimmed() {
+        let mut renderer = Renderer::new(RenderMode::Block, Theme::AMBER.with_foreground((200, 100, 0)));
+        let mut state = Chip8State::new();
+        let mut pixels = Pixels::new();
+        state.display.draw_sprite(0, 0, &[0x80], false);
+        pixels.update(&state, Persistence::Phosphor);
+        render(&mut renderer, &frame_with(&state, &pixels));
+        state.display.draw_sprite(0, 0, &[0x80], false);
+        pixels.update(&state, Persistence::Phosphor);
+        assert!(render(&mut renderer, &frame_with(&state, &pixels)).starts_with(
+            &format!("{}{}{}░", cursor::Goto(8, 4), color::Fg(color::Rgb(150, 75, 0)), color::Bg(color::Rgb(25, 15, 0)))));
+        let mut renderer = Renderer::new(RenderMode::HalfBlock, Theme::default());
+        let mut pixels = Pixels::new();
+        state.display.draw_sprite(0, 1, &[0x80], false);
+        pixels.update(&state, Persistence::Phosphor);
+        render(&mut renderer, &frame_with(&state, &pixels));
+        state.display.draw_sprite(0, 1, &[0x80], false);
+        // terminal colours can't be dimmed: fading pixels stay until gone
+        for _ in 1..LIT {
+            pixels.update(&state, Persistence::Phosphor);
+            assert_eq!(render(&mut renderer, &frame_with(&state, &pixels)), "");
+        }
+        pixels.update(&state, Persistence::Phosphor);
+        assert_eq!(render(&mut renderer, &frame_with(&state, &pixels)), format!("{} {}", cursor::Goto(8, 4), cursor::Goto(1, 22)));
+    }
+
+    #[test]
+    fn removed_text_is_blanked() {
+        let mut renderer = Renderer::new(RenderMode::Block, Theme::default());
+        let mut with_message = frame(&Chip8State::new());
+        with_message.message = Some(String::from("Saved"));
+        render(&mut renderer, &with_message);
+        assert_eq!(render(&mut renderer, &frame(&Chip8State::new())),
+                   format!("{}     {}", cursor::Goto(2, 38), cursor::Goto(1, 39)));
+    }
+
     #[ignore]
     #[bench]
     fn display_bench(b: &mut Bencher) {
-        let ch8state = Chip8State::new();
-        b.iter(|| display(ch8state));
+        let frame = frame(&Chip8State::new());
+        b.iter(|| Renderer::new(RenderMode::Block, Theme::default()).render(&mut io::sink(), &frame));
     }
 }
@@ -24,7 +24,25 @@ fn read_instruction() {
     let mut ch8state = Chip8State::new();
     let data: &[u8] = &[1,2];
     ch8state = ch8state.load(data);
-    assert_eq!(ch8state.read_instruction(), Chip8Instruction(0x0102));
+    assert_eq!(ch8state.read_instruction(), Ok(Chip8Instruction(0x0102)));
+}
+
+#[test]
+fn read_instruction_out_of_memory() {
+    let mut ch8state = Chip8State::new();
+    ch8state.pc = 0xFFF;
+    assert_eq!(ch8state.read_instruction(), Err(Chip8Error::PcOutOfRange { pc: 0xFFF }));
+    ch8state.pc = 0xFFE;
+    assert_eq!(ch8state.read_instruction(), Ok(Chip8Instruction(0)));
+}
+
+#[test]
+fn unknown_instruction() {
+    let mut ch8state = Chip8State::new();
+    ch8state = ch8state.load(&[0x00,0xE0,0x5A,0xB1]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.exec_instruction().err(),
+               Some(Chip8Error::UnknownOpcode { opcode: 0x5AB1, addr: 0x202 }));
 }
 
 #[test]
@@ -49,12 +67,12 @@ fn clear_screen_instruction() {
         // load instruction to draw + to clear
         ch8state = ch8state.load(&[0xD0, 0x16, 0x00, 0xE0]);
         // run draw
-        ch8state = ch8state.exec_instruction();
+        ch8state = ch8state.exec_instruction().unwrap();
         assert_eq!(ch8state.display.get_pixel(0,0), true);
     }
     // run cls
-    assert_eq!(ch8state.read_instruction(), Chip8Instruction(0x00E0));
-    ch8state = ch8state.exec_instruction();
+    assert_eq!(ch8state.read_instruction(), Ok(Chip8Instruction(0x00E0)));
+    ch8state = ch8state.exec_instruction().unwrap();
     for c in 0..64 {
         for r in 0..32 {
             assert_eq!(ch8state.display.get_pixel(c,r), false);
@@ -62,12 +80,54 @@ fn clear_screen_instruction() {
     }
 }
 
+#[test]
+fn hires_instructions() {
+    let mut ch8state = Chip8State::new();
+    ch8state = ch8state.load(&[0x00,0xFF,0x00,0xFE]);
+    assert_eq!(ch8state.display_size(), (64, 32));
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x202, "Incorrect program counter");
+    assert_eq!(ch8state.display_size(), (128, 64));
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x204, "Incorrect program counter");
+    assert_eq!(ch8state.display_size(), (64, 32));
+}
+
+#[test]
+fn scroll_instructions() {
+    let mut ch8state = Chip8State::new();
+    ch8state.display.draw_sprite(8, 8, &[0x80], false);
+    // scroll down 3, right 4, left 4
+    ch8state = ch8state.load(&[0x00,0xC3,0x00,0xFB,0x00,0xFC]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert!(!ch8state.get_pixel(8, 8));
+    assert!(ch8state.get_pixel(8, 11));
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert!(ch8state.get_pixel(12, 11));
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert!(!ch8state.get_pixel(12, 11));
+    assert!(ch8state.get_pixel(8, 11));
+    assert_eq!(ch8state.pc, 0x206, "Incorrect program counter");
+}
+
+#[test]
+fn exit_instruction() {
+    let mut ch8state = Chip8State::new();
+    ch8state = ch8state.load(&[0x00,0xFD,0x60,0x01]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert!(ch8state.is_halted());
+    // nothing else gets executed
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x200, "Incorrect program counter");
+    assert_eq!(ch8state.reg[0], 0);
+}
+
 #[test]
 fn read_return_address() {
     let mut ch8state = Chip8State::new();
     // set some ret address
-    ch8state.ram[ch8state.sp as usize] = 0x0A;
-    ch8state.ram[(ch8state.sp+1) as usize] = 0xBC;
+    ch8state.stack[0] = 0x0ABC;
+    ch8state.sp = 1;
     // check return
     assert_eq!(ch8state.read_return_address(), 0x0ABC);
 }
@@ -75,24 +135,85 @@ fn read_return_address() {
 #[test]
 fn return_from_subroutine_instruction() {
     let mut ch8state = Chip8State::new();
+    // pretend a subroutine was called, setting some ret address
+    ch8state.stack[0] = 0x0ABC;
+    ch8state.sp += 1;
     let initial_sp = ch8state.sp;
-    // set some ret address
-    ch8state.ram[ch8state.sp as usize] = 0x0A;
-    ch8state.ram[(ch8state.sp+1) as usize] = 0xBC;
     // load return instruction and execute
     ch8state = ch8state.load(&[0x00,0xEE]);
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
-    assert_eq!(ch8state.sp, initial_sp-2);
+    assert_eq!(ch8state.sp, initial_sp-1);
     assert_eq!(ch8state.pc, 0x0ABC);
 }
 
+#[test]
+fn return_with_empty_stack() {
+    let mut ch8state = Chip8State::new();
+    ch8state = ch8state.load(&[0x00,0xEE]);
+    assert_eq!(ch8state.exec_instruction().err(),
+               Some(Chip8Error::StackUnderflow { addr: 0x200 }));
+}
+
+#[test]
+fn call_with_full_stack() {
+    let mut ch8state = Chip8State::new();
+    // load a subroutine that keeps calling itself
+    ch8state = ch8state.load(&[0x22,0x00]);
+    let mut calls = 0;
+    loop {
+        match ch8state.exec_instruction() {
+            Ok(st) => ch8state = st,
+            Err(e) => {
+                assert_eq!(e, Chip8Error::StackOverflow { addr: 0x200 });
+                break;
+            }
+        }
+        calls += 1;
+    }
+    assert_eq!(calls, 16, "Incorrect stack depth");
+    assert_eq!(ch8state.sp as usize, STACK_DEPTH);
+}
+
+#[test]
+fn xo_chip_call_keeps_program_memory() {
+    // a program filling memory past 0xEA0, calling a subroutine placed there
+    let mut rom = vec![0; 0x1000];
+    rom[0..2].copy_from_slice(&[0x2E, 0xA0]);
+    rom[0xCA0..0xCA4].copy_from_slice(&[0x60, 0x2A, 0x00, 0xEE]);
+    rom[0xCA4..0xCA8].copy_from_slice(&[0xAB, 0xCD, 0xEF, 0x01]);
+    let mut ch8state = Chip8State::new().with_xo_chip(true).load(&rom);
+    for _ in 0..3 {
+        ch8state = ch8state.exec_instruction().unwrap();
+    }
+    assert_eq!(ch8state.pc, 0x202);
+    assert_eq!(ch8state.reg[0], 0x2A);
+    assert_eq!(&ch8state.ram[0xEA0..0xEA8], &[0x60, 0x2A, 0x00, 0xEE, 0xAB, 0xCD, 0xEF, 0x01]);
+}
+
+#[test]
+fn xo_chip_pc_wraps_at_end_of_memory() {
+    // instructions at the last address of the XO-CHIP address space
+    let mut ch8state = Chip8State::new().with_xo_chip(true);
+    ch8state.ram[0xFFFE..].copy_from_slice(&[0x60, 0x2A]);
+    ch8state.pc = 0xFFFE;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x0000);
+    assert_eq!(ch8state.reg[0], 0x2A);
+    // a call there returns to the start of memory
+    ch8state.ram[0xFFFE..].copy_from_slice(&[0x23, 0x00]);
+    ch8state.pc = 0xFFFE;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x0300);
+    assert_eq!(ch8state.call_stack(), vec![0x0000]);
+}
+
 #[test]
 fn jump_instruction() {
     let mut ch8state = Chip8State::new();
     // load return instruction and execute
     ch8state = ch8state.load(&[0x1A,0xBC]);
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, 0x0ABC);
 }
@@ -103,19 +224,29 @@ fn indexed_jump_instruction() {
     // load return instruction and execute
     ch8state = ch8state.load(&[0xBA,0xB0]);
     ch8state.reg[0] = 0xC;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, 0x0ABC);
 }
 
+#[test]
+fn indexed_jump_quirk_uses_vx() {
+    let mut ch8state = Chip8State::new().with_quirks(Quirks::SUPER_CHIP);
+    ch8state = ch8state.load(&[0xBA,0xB0]);
+    ch8state.reg[0] = 0x1;
+    ch8state.reg[0xA] = 0xC;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x0ABC);
+}
+
 #[test]
 fn call_instruction() {
     let initial_ch8state = Chip8State::new();
     // load return instruction and execute
     let ch8state1 = initial_ch8state.load(&[0x2A,0xBC]);
-    let ch8state2 = ch8state1.exec_instruction();
+    let ch8state2 = ch8state1.exec_instruction().unwrap();
     // check state
-    assert_eq!(ch8state2.sp, ch8state1.sp+2, "Incorrect SP register value");
+    assert_eq!(ch8state2.sp, ch8state1.sp+1, "Incorrect SP register value");
     assert_eq!(ch8state2.read_return_address(), ch8state1.pc+2, "Incorrect return address");
     assert_eq!(ch8state2.pc, 0xABC, "Incorrect PC value");
 }
@@ -127,7 +258,7 @@ fn skip_if_equals_immediate_positive() {
     ch8state = ch8state.load(&[0x30,0x12]);
     ch8state.reg[0] = 0x12;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+4, "Incorrect PC register value");
 }
@@ -139,7 +270,7 @@ fn skip_if_equals_immediate_negative() {
     ch8state = ch8state.load(&[0x30,0x12]);
     // ch8state.reg[0] = 0x00;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect PC register value");
 }
@@ -150,7 +281,7 @@ fn skip_if_not_equals_immediate_positive() {
     // load return instruction and execute
     ch8state = ch8state.load(&[0x40,0x12]);
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+4, "Incorrect PC register value");
 }
@@ -162,7 +293,7 @@ fn skip_if_not_equals_immediate_negative() {
     ch8state = ch8state.load(&[0x40,0x12]);
     ch8state.reg[0] = 0x12;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect PC register value");
 }
@@ -173,7 +304,7 @@ fn skip_if_equals_registers_positive() {
     // load return instruction and execute
     ch8state = ch8state.load(&[0x50,0x10]);
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+4, "Incorrect PC register value");
 }
@@ -185,7 +316,7 @@ fn skip_if_equals_registers_negative() {
     ch8state = ch8state.load(&[0x50,0x10]);
     ch8state.reg[0] = 1;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect PC register value");
 }
@@ -197,7 +328,7 @@ fn skip_if_registers_not_equal_positive() {
     ch8state = ch8state.load(&[0x90,0x10]);
     ch8state.reg[0] = 1;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+4, "Incorrect PC register value");
 }
@@ -208,7 +339,7 @@ fn skip_if_registers_not_equal_negative() {
     // load return instruction and execute
     ch8state = ch8state.load(&[0x90,0x10]);
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect PC register value");
 }
@@ -219,7 +350,7 @@ fn skip_if_key_pressed_negative_case() {
     // load return instruction and execute
     ch8state = ch8state.load(&[0xE0,0x9E]);
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect PC register value");
 }
@@ -232,7 +363,7 @@ fn skip_if_key_pressed_negative_case_after_release() {
     let pc_pre = ch8state.pc;
     ch8state = ch8state.key_down(0);
     ch8state = ch8state.key_up(0);
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect PC register value");
 }
@@ -245,7 +376,7 @@ fn skip_if_key_pressed_positive_case() {
     ch8state.reg[1] = 0xF;
     let pc_pre = ch8state.pc;
     ch8state = ch8state.key_down(0xF);
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+4, "Incorrect PC register value");
 }
@@ -258,7 +389,7 @@ fn skip_if_key_not_pressed_when_pressed() {
     ch8state.reg[1] = 0xF;
     let pc_pre = ch8state.pc;
     ch8state = ch8state.key_down(0xF);
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect PC register value");
 }
@@ -270,7 +401,7 @@ fn skip_if_key_not_pressed_when_not_pressed() {
     ch8state = ch8state.load(&[0xE1,0xA1]);
     ch8state.reg[1] = 0xF;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+4, "Incorrect PC register value");
 }
@@ -283,7 +414,7 @@ fn skip_if_key_not_pressed_when_released() {
     let pc_pre = ch8state.pc;
     ch8state = ch8state.key_down(0);
     ch8state = ch8state.key_up(0);
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+4, "Incorrect PC register value");
 }
@@ -295,7 +426,7 @@ fn load_immediate() {
     ch8state = ch8state.load(&[0x60,0x12]);
     assert_eq!(ch8state.reg[0], 0);
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 0x12, "Incorrect register value");
@@ -308,7 +439,7 @@ fn add_immediate() {
     ch8state = ch8state.load(&[0x70,0x12]);
     ch8state.reg[0] = 1;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 0x13, "Incorrect register value");
@@ -322,7 +453,7 @@ fn move_register() {
     ch8state.reg[0] = 1;
     assert_eq!(ch8state.reg[1], 0, "Incorrect register value");
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 1, "Incorrect register value");
@@ -337,7 +468,7 @@ fn or_register() {
     ch8state.reg[0] = 0x03;
     ch8state.reg[1] = 0x11;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 0x03, "Incorrect register value");
@@ -352,7 +483,7 @@ fn and_register() {
     ch8state.reg[0] = 0x03;
     ch8state.reg[1] = 0x11;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 0x03, "Incorrect register value");
@@ -367,13 +498,24 @@ fn xor_register() {
     ch8state.reg[0] = 0x03;
     ch8state.reg[1] = 0x11;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 0x03, "Incorrect register value");
     assert_eq!(ch8state.reg[1], 0x12, "Incorrect register value");
 }
 
+#[test]
+fn logic_quirk_resets_vf() {
+    for op in [0x01, 0x02, 0x03].iter() {
+        let mut ch8state = Chip8State::new().with_quirks(Quirks::COSMAC_VIP);
+        ch8state = ch8state.load(&[0x80, *op]);
+        ch8state.reg[0xF] = 1;
+        ch8state = ch8state.exec_instruction().unwrap();
+        assert_eq!(ch8state.reg[0xF], 0, "Incorrect register value");
+    }
+}
+
 #[test]
 fn add_registers_no_carry() {
     let mut ch8state = Chip8State::new();
@@ -382,7 +524,7 @@ fn add_registers_no_carry() {
     ch8state.reg[0] = 0x03;
     ch8state.reg[1] = 0x11;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 0x03, "Incorrect register value");
@@ -398,7 +540,7 @@ fn add_registers_carry() {
     ch8state.reg[0] = 0xFF;
     ch8state.reg[1] = 0x03;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 0xFF, "Incorrect register value");
@@ -414,7 +556,7 @@ fn sub_registers_borrow() {
     ch8state.reg[0] = 1;
     ch8state.reg[1] = 0;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 1, "Incorrect register value");
@@ -430,7 +572,7 @@ fn sub_registers_no_borrow() {
     ch8state.reg[0] = 1;
     ch8state.reg[1] = 3;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 1, "Incorrect register value");
@@ -445,7 +587,7 @@ fn shr_register_no_carry() {
     ch8state = ch8state.load(&[0x80,0x06]);
     ch8state.reg[0] = 2;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 1, "Incorrect register value");
@@ -459,13 +601,25 @@ fn shr_register_carry() {
     ch8state = ch8state.load(&[0x80,0x06]);
     ch8state.reg[0] = 3;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 1, "Incorrect register value");
     assert_eq!(ch8state.reg[0xF], 1, "Incorrect register value");
 }
 
+#[test]
+fn shr_register_quirk_uses_vy() {
+    let mut ch8state = Chip8State::new().with_quirks(Quirks::COSMAC_VIP);
+    ch8state = ch8state.load(&[0x80,0x16]);
+    ch8state.reg[0] = 0x10;
+    ch8state.reg[1] = 0x03;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.reg[0], 0x01, "Incorrect register value");
+    assert_eq!(ch8state.reg[1], 0x03, "Incorrect register value");
+    assert_eq!(ch8state.reg[0xF], 1, "Incorrect register value");
+}
+
 #[test]
 fn subn_registers_borrow() {
     let mut ch8state = Chip8State::new();
@@ -474,7 +628,7 @@ fn subn_registers_borrow() {
     ch8state.reg[0] = 1;
     ch8state.reg[1] = 0;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[1], 0, "Incorrect register value");
@@ -490,7 +644,7 @@ fn subn_registers_no_borrow() {
     ch8state.reg[0] = 1;
     ch8state.reg[1] = 2;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 1, "Incorrect register value");
@@ -498,6 +652,17 @@ fn subn_registers_no_borrow() {
     assert_eq!(ch8state.reg[0xF], 1, "Incorrect register value");
 }
 
+#[test]
+fn subn_registers_flag_wins_over_result_in_vf() {
+    let mut ch8state = Chip8State::new();
+    // VF = V0 - VF
+    ch8state = ch8state.load(&[0x8F,0x07]);
+    ch8state.reg[0] = 5;
+    ch8state.reg[0xF] = 7;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.reg[0xF], 0, "Incorrect register value");
+}
+
 #[test]
 fn shl_register_no_carry() {
     let mut ch8state = Chip8State::new();
@@ -505,7 +670,7 @@ fn shl_register_no_carry() {
     ch8state = ch8state.load(&[0x80,0x0E]);
     ch8state.reg[0] = 2;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 4, "Incorrect register value");
@@ -519,13 +684,25 @@ fn shl_register_carry() {
     ch8state = ch8state.load(&[0x80,0x0E]);
     ch8state.reg[0] = 0x83;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0], 6, "Incorrect register value");
     assert_eq!(ch8state.reg[0xF], 1, "Incorrect register value");
 }
 
+#[test]
+fn shl_register_quirk_uses_vy() {
+    let mut ch8state = Chip8State::new().with_quirks(Quirks::COSMAC_VIP);
+    ch8state = ch8state.load(&[0x80,0x1E]);
+    ch8state.reg[0] = 0x01;
+    ch8state.reg[1] = 0x81;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.reg[0], 0x02, "Incorrect register value");
+    assert_eq!(ch8state.reg[1], 0x81, "Incorrect register value");
+    assert_eq!(ch8state.reg[0xF], 1, "Incorrect register value");
+}
+
 #[test]
 fn set_i() {
     let mut ch8state = Chip8State::new();
@@ -533,7 +710,7 @@ fn set_i() {
     ch8state = ch8state.load(&[0xA1,0x23]);
     assert_eq!(ch8state.i, 0);
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.i, 0x123, "Incorrect register value");
@@ -546,12 +723,27 @@ fn masked_random() {
     ch8state = ch8state.load(&[0xC0,0x0F]);
     ch8state.reg[0] = 0xFF;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     // check state
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert!(ch8state.reg[0] <= 0xF, "Incorrect register value");
 }
 
+#[test]
+fn masked_random_is_seeded() {
+    // 200: V0 := random; 202: V1 := random
+    let program = [0xC0,0xFF,0xC1,0xFF];
+    let run = |rng| {
+        let mut ch8state = Chip8State::with_random(rng).load(&program);
+        ch8state = ch8state.exec_instruction().unwrap();
+        ch8state = ch8state.exec_instruction().unwrap();
+        (ch8state.reg[0], ch8state.reg[1])
+    };
+    assert_eq!(run(BuiltinRandom::xorshift(5)), run(BuiltinRandom::xorshift(5)));
+    let mut rng = BuiltinRandom::xorshift(5);
+    assert_eq!(run(BuiltinRandom::xorshift(5)), (rng.next_byte(), rng.next_byte()));
+}
+
 #[test]
 fn draw_sprite_no_collision() {
     let mut ch8state = Chip8State::new();
@@ -575,7 +767,7 @@ fn draw_sprite_no_collision() {
     ch8state = ch8state.load(drw_instruction);
     // run it
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     // check just some pixels, e.g. a sprite diagonal
     assert_eq!(ch8state.display.get_pixel(0,0), true, "Incorrect register value");
@@ -602,21 +794,116 @@ fn draw_sprite_with_collision() {
     ch8state = ch8state.load(&[0xD0,0x16,0xD0,0x16]);
     // run it
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[0xF], 0, "Incorrect collision detection");
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+4, "Incorrect program counter");
     assert_eq!(ch8state.reg[0xF], 1, "Incorrect collision detection");
 }
 
+#[test]
+fn draw_sprite_out_of_memory() {
+    let mut ch8state = Chip8State::new();
+    ch8state.i = 0xFFC;
+    ch8state = ch8state.load(&[0xD0,0x15]);
+    assert_eq!(ch8state.exec_instruction().err(),
+               Some(Chip8Error::MemoryOutOfRange { pc: 0x200, addr: 0x1000 }));
+}
+
+#[test]
+fn draw_sprite_at_large_coordinates() {
+    let mut ch8state = Chip8State::new();
+    ch8state.ram[0x300..0x302].copy_from_slice(&[0xC0, 0xC0]);
+    ch8state.i = 0x300;
+    // 255 % 64 = 63, 254 % 32 = 30
+    ch8state.reg[0] = 255;
+    ch8state.reg[1] = 254;
+    ch8state = ch8state.load(&[0xD0,0x12]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert!(ch8state.get_pixel(63, 30));
+    assert!(ch8state.get_pixel(63, 31));
+    assert!(!ch8state.get_pixel(0, 30));
+    assert_eq!(ch8state.reg[0xF], 0, "Incorrect collision detection");
+}
+
+#[test]
+fn draw_sprite_quirk_wraps() {
+    let quirks = Quirks { wrap_sprites: true, ..Quirks::default() };
+    let mut ch8state = Chip8State::new().with_quirks(quirks);
+    ch8state.ram[0x300..0x302].copy_from_slice(&[0xC0, 0xC0]);
+    ch8state.i = 0x300;
+    ch8state.reg[0] = 63;
+    ch8state.reg[1] = 31;
+    ch8state = ch8state.load(&[0xD0,0x12,0xD0,0x12]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    for &(col, row) in &[(63, 31), (0, 31), (63, 0), (0, 0)] {
+        assert!(ch8state.get_pixel(col, row));
+    }
+    assert_eq!(ch8state.reg[0xF], 0, "Incorrect collision detection");
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert!(!ch8state.get_pixel(0, 0));
+    assert_eq!(ch8state.reg[0xF], 1, "Incorrect collision detection");
+}
+
+#[test]
+fn draw_sprite_quirk_waits_for_vblank() {
+    let quirks = Quirks { display_wait: true, ..Quirks::default() };
+    let mut ch8state = Chip8State::new().with_quirks(quirks);
+    ch8state = ch8state.load(&[0xD0,0x11,0xD0,0x11]);
+    ch8state.t_delay = 2;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x202, "Incorrect program counter");
+    assert!(ch8state.is_waiting_for_vblank());
+    // no more instructions run until the frame ends
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x202, "Incorrect program counter");
+    ch8state = ch8state.vblank();
+    assert!(!ch8state.is_waiting_for_vblank());
+    assert_eq!(ch8state.t_delay, 1);
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x204, "Incorrect program counter");
+    assert!(ch8state.is_waiting_for_vblank());
+}
+
+#[test]
+fn draw_sprite_does_not_wait_without_quirk() {
+    let mut ch8state = Chip8State::new();
+    ch8state = ch8state.load(&[0xD0,0x11,0xD0,0x11]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x204, "Incorrect program counter");
+    assert!(!ch8state.is_waiting_for_vblank());
+}
+
+#[test]
+fn draw_wide_sprite() {
+    let mut ch8state = Chip8State::new();
+    ch8state.ram[0x300..0x320].copy_from_slice(&[0xFF; 32]);
+    ch8state.i = 0x300;
+    ch8state.reg[0] = 2;
+    ch8state.reg[1] = 3;
+    ch8state = ch8state.load(&[0x00,0xFF,0xD0,0x10,0xD0,0x10]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x204, "Incorrect program counter");
+    assert!(ch8state.get_pixel(2, 3));
+    assert!(ch8state.get_pixel(17, 18));
+    assert!(!ch8state.get_pixel(18, 18));
+    assert!(!ch8state.get_pixel(17, 19));
+    assert_eq!(ch8state.reg[0xF], 0, "Incorrect collision detection");
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert!(!ch8state.get_pixel(2, 3));
+    assert_eq!(ch8state.reg[0xF], 1, "Incorrect collision detection");
+}
+
 #[test]
 fn move_delay_timer_value_to_register() {
     let mut ch8state = Chip8State::new();
     ch8state.t_delay = 123;
     ch8state = ch8state.load(&[0xF2,0x07]);
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.reg[2], 123);
 }
@@ -626,11 +913,11 @@ fn wait_for_key() {
     let mut ch8state = Chip8State::new();
     ch8state = ch8state.load(&[0xF3,0x0A]);
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre, "Incorrect program counter");
     assert_eq!(ch8state.reg[3], 0);
     assert_eq!(ch8state.waiting_for_key, Some(3));
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre, "Incorrect program counter");
     assert_eq!(ch8state.reg[3], 0);
     assert_eq!(ch8state.waiting_for_key, Some(3));
@@ -641,7 +928,7 @@ fn key_arrives_while_waiting_for_key() {
     let mut ch8state = Chip8State::new();
     ch8state = ch8state.load(&[0xF7,0x0A]);
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre, "Incorrect program counter");
     assert_eq!(ch8state.reg[7], 0);
     assert_eq!(ch8state.waiting_for_key, Some(7));
@@ -651,13 +938,51 @@ fn key_arrives_while_waiting_for_key() {
     assert_eq!(ch8state.waiting_for_key, None);
 }
 
+#[test]
+fn key_held_before_waiting_for_key() {
+    let mut ch8state = Chip8State::new();
+    ch8state = ch8state.load(&[0xF7,0x0A]);
+    ch8state = ch8state.key_down(0xC);
+    ch8state = ch8state.exec_instruction().unwrap();
+    // the key was already down, so repeating it doesn't count
+    ch8state = ch8state.key_down(0xC);
+    assert_eq!(ch8state.waiting_for_key, Some(7));
+    ch8state = ch8state.key_up(0xC);
+    ch8state = ch8state.key_down(0xC);
+    assert_eq!(ch8state.pc, 0x202, "Incorrect program counter");
+    assert_eq!(ch8state.reg[7], 0xC);
+}
+
+#[test]
+fn wait_for_key_quirk_waits_for_release() {
+    let quirks = Quirks { key_wait_release: true, ..Quirks::default() };
+    let mut ch8state = Chip8State::new().with_quirks(quirks);
+    ch8state = ch8state.load(&[0xF7,0x0A]);
+    ch8state = ch8state.key_down(0x1);
+    ch8state = ch8state.exec_instruction().unwrap();
+    // releasing a key held from before doesn't count either
+    ch8state = ch8state.key_up(0x1);
+    assert_eq!(ch8state.waiting_for_key, Some(7));
+    ch8state = ch8state.key_down(0xC);
+    ch8state = ch8state.key_down(0x5);
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x200, "Incorrect program counter");
+    // only the first key pressed is waited for
+    ch8state = ch8state.key_up(0x5);
+    assert_eq!(ch8state.waiting_for_key, Some(7));
+    ch8state = ch8state.key_up(0xC);
+    assert_eq!(ch8state.pc, 0x202, "Incorrect program counter");
+    assert_eq!(ch8state.reg[7], 0xC);
+    assert_eq!(ch8state.waiting_for_key, None);
+}
+
 #[test]
 fn set_delay_timer() {
     let mut ch8state = Chip8State::new();
     ch8state = ch8state.load(&[0xF9,0x15]);
     ch8state.reg[9] = 60;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.t_delay, 60);
 }
@@ -668,7 +993,7 @@ fn set_sound_timer() {
     ch8state = ch8state.load(&[0xF9,0x18]);
     ch8state.reg[9] = 60;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.t_sound, 60);
 }
@@ -680,11 +1005,39 @@ fn add_register_to_i() {
     ch8state.i = 0x300;
     ch8state.reg[4] = 1;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.i, 0x301);
 }
 
+#[test]
+fn add_register_to_i_quirk_sets_vf() {
+    let quirks = Quirks { i_overflow_sets_vf: true, ..Quirks::default() };
+    let mut ch8state = Chip8State::new().with_quirks(quirks);
+    ch8state = ch8state.load(&[0xF4,0x1E,0xF4,0x1E]);
+    ch8state.i = 0xFFE;
+    ch8state.reg[4] = 1;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.i, 0xFFF);
+    assert_eq!(ch8state.reg[0xF], 0);
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.i, 0x1000);
+    assert_eq!(ch8state.reg[0xF], 1);
+}
+
+#[test]
+fn add_register_to_i_quirk_leaves_vf_in_xo_chip_mode() {
+    let quirks = Quirks { i_overflow_sets_vf: true, ..Quirks::default() };
+    let mut ch8state = Chip8State::new().with_quirks(quirks).with_xo_chip(true);
+    ch8state = ch8state.load(&[0xF4,0x1E]);
+    ch8state.i = 0xFFF;
+    ch8state.reg[4] = 1;
+    ch8state.reg[0xF] = 7;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.i, 0x1000);
+    assert_eq!(ch8state.reg[0xF], 7);
+}
+
 #[test]
 fn font_sprites_are_loaded() {
     let ch8state = Chip8State::new();
@@ -699,11 +1052,30 @@ fn location_of_sprite() {
     ch8state = ch8state.load(&[0xF4,0x29]);
     ch8state.reg[4] = 0xF;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.i, 0xF*5);
 }
 
+#[test]
+fn big_font_sprites_are_loaded() {
+    let ch8state = Chip8State::new();
+    for (index, byte) in BIG_FONT_SPRITES.iter().enumerate() {
+        assert_eq!(ch8state.ram[BIG_FONT_ADDR + index], *byte);
+    }
+}
+
+#[test]
+fn location_of_big_sprite() {
+    let mut ch8state = Chip8State::new();
+    ch8state = ch8state.load(&[0xF4,0x30]);
+    ch8state.reg[4] = 0x9;
+    let pc_pre = ch8state.pc;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
+    assert_eq!(ch8state.i, 0x50 + 9*10);
+}
+
 #[test]
 fn binary_coded_decimal_conversion() {
     let mut ch8state = Chip8State::new();
@@ -711,13 +1083,22 @@ fn binary_coded_decimal_conversion() {
     ch8state.reg[0] = 0xFE;
     ch8state.i = 0x400;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     assert_eq!(ch8state.ram[0x400], 2);
     assert_eq!(ch8state.ram[0x401], 5);
     assert_eq!(ch8state.ram[0x402], 4);
 }
 
+#[test]
+fn binary_coded_decimal_conversion_out_of_memory() {
+    let mut ch8state = Chip8State::new();
+    ch8state = ch8state.load(&[0xF0,0x33]);
+    ch8state.i = 0xFFE;
+    assert_eq!(ch8state.exec_instruction().err(),
+               Some(Chip8Error::MemoryOutOfRange { pc: 0x200, addr: 0x1000 }));
+}
+
 #[test]
 fn dump_all_registers() {
     let mut ch8state = Chip8State::new();
@@ -725,7 +1106,7 @@ fn dump_all_registers() {
     ch8state.reg = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
     ch8state.i = 0x400;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     for (index, value) in ch8state.reg.iter().enumerate() {
         assert_eq!(ch8state.ram[0x400+index], *value);
@@ -739,7 +1120,7 @@ fn dump_some_registers() {
     ch8state.reg = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
     ch8state.i = 0x400;
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     for (index, value) in ch8state.reg.iter().enumerate() {
         if index <= 5 {
@@ -750,6 +1131,20 @@ fn dump_some_registers() {
     }
 }
 
+#[test]
+fn dump_registers_quirk_increments_i() {
+    let mut ch8state = Chip8State::new().with_quirks(Quirks::COSMAC_VIP);
+    ch8state = ch8state.load(&[0xF5,0x55]);
+    ch8state.i = 0x400;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.i, 0x406);
+    let mut ch8state = Chip8State::new().with_quirks(Quirks::CHIP_48);
+    ch8state = ch8state.load(&[0xF5,0x55]);
+    ch8state.i = 0x400;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.i, 0x405);
+}
+
 #[test]
 fn load_all_registers() {
     let mut ch8state = Chip8State::new();
@@ -758,7 +1153,7 @@ fn load_all_registers() {
     ch8state.i = 0x400;
     (&mut ch8state.ram[0x400..0x410]).copy_from_slice(&samples);
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     for (index, value) in ch8state.reg.iter().enumerate() {
         assert_eq!(*value, ch8state.ram[0x400+index]);
@@ -773,7 +1168,7 @@ fn load_some_registers() {
     ch8state.i = 0x400;
     (&mut ch8state.ram[0x400..0x410]).copy_from_slice(&samples);
     let pc_pre = ch8state.pc;
-    ch8state = ch8state.exec_instruction();
+    ch8state = ch8state.exec_instruction().unwrap();
     assert_eq!(ch8state.pc, pc_pre+2, "Incorrect program counter");
     for (index, value) in ch8state.reg.iter().enumerate() {
         if index > 7 {
@@ -784,6 +1179,45 @@ fn load_some_registers() {
     }
 }
 
+#[test]
+fn load_registers_out_of_memory() {
+    let mut ch8state = Chip8State::new();
+    ch8state = ch8state.load(&[0xFF,0x65]);
+    ch8state.i = 0xFF8;
+    assert_eq!(ch8state.exec_instruction().err(),
+               Some(Chip8Error::MemoryOutOfRange { pc: 0x200, addr: 0x1007 }));
+}
+
+#[test]
+fn load_registers_quirk_increments_i() {
+    let mut ch8state = Chip8State::new().with_quirks(Quirks::COSMAC_VIP);
+    ch8state = ch8state.load(&[0xF3,0x65]);
+    ch8state.i = 0x400;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.i, 0x404);
+}
+
+#[test]
+fn save_and_load_flags() {
+    let mut ch8state = Chip8State::new();
+    ch8state = ch8state.load(&[0xF3,0x75,0xF7,0x85]);
+    ch8state.reg = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(&ch8state.rpl[0..8], &[0, 1, 2, 3, 0, 0, 0, 0]);
+    ch8state.reg = [0xFF; 16];
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x204, "Incorrect program counter");
+    assert_eq!(&ch8state.reg[0..9], &[0, 1, 2, 3, 0, 0, 0, 0, 0xFF]);
+}
+
+#[test]
+fn save_too_many_flags() {
+    let mut ch8state = Chip8State::new();
+    ch8state = ch8state.load(&[0xF8,0x75]);
+    assert_eq!(ch8state.exec_instruction().err(),
+               Some(Chip8Error::UnknownOpcode { opcode: 0xF875, addr: 0x200 }));
+}
+
 #[test]
 fn decrease_timers() {
     let mut ch8state = Chip8State::new();
@@ -799,3 +1233,211 @@ fn decrease_timers() {
     assert_eq!(ch8state.t_delay, 0);
     assert_eq!(ch8state.t_sound, 0);
 }
+
+#[test]
+fn xo_chip_instructions_need_xo_chip_mode() {
+    let mut ch8state = Chip8State::new();
+    ch8state = ch8state.load(&[0xF0,0x00,0x12,0x34]);
+    assert_eq!(ch8state.exec_instruction().err(),
+               Some(Chip8Error::UnknownOpcode { opcode: 0xF000, addr: 0x200 }));
+}
+
+#[test]
+fn set_long_i() {
+    let mut ch8state = Chip8State::new().with_xo_chip(true);
+    ch8state = ch8state.load(&[0xF0,0x00,0xAB,0xCD]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x204, "Incorrect program counter");
+    assert_eq!(ch8state.i, 0xABCD);
+}
+
+#[test]
+fn skip_over_long_instruction() {
+    let mut ch8state = Chip8State::new().with_xo_chip(true);
+    ch8state = ch8state.load(&[0x30,0x00,0xF0,0x00,0xAB,0xCD]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.pc, 0x206, "Incorrect program counter");
+}
+
+#[test]
+fn xo_chip_memory_access() {
+    let mut ch8state = Chip8State::new().with_xo_chip(true);
+    ch8state = ch8state.load(&[0xF3,0x55]);
+    ch8state.reg[3] = 0x42;
+    ch8state.i = 0xFFFC;
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.ram[0xFFFF], 0x42);
+}
+
+#[test]
+fn dump_and_load_register_range() {
+    let mut ch8state = Chip8State::new().with_xo_chip(true);
+    ch8state = ch8state.load(&[0x52,0x42,0x54,0x22,0x50,0x33]);
+    ch8state.reg = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+    ch8state.i = 0x400;
+    // store V2..V4
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(&ch8state.ram[0x400..0x404], &[2, 3, 4, 0]);
+    assert_eq!(ch8state.i, 0x400);
+    // store V4..V2, backwards
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(&ch8state.ram[0x400..0x404], &[4, 3, 2, 0]);
+    // load V0..V3
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(&ch8state.reg[0..5], &[4, 3, 2, 0, 4]);
+    assert_eq!(ch8state.pc, 0x206, "Incorrect program counter");
+}
+
+#[test]
+fn select_planes_and_draw() {
+    let mut ch8state = Chip8State::new().with_xo_chip(true);
+    ch8state.ram[0x300..0x302].copy_from_slice(&[0x80, 0xC0]);
+    ch8state.i = 0x300;
+    ch8state = ch8state.load(&[0xF3,0x01,0xD0,0x01]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.get_color(0, 0), 3);
+    assert_eq!(ch8state.get_color(1, 0), 2);
+}
+
+#[test]
+fn scroll_up_instruction() {
+    let mut ch8state = Chip8State::new().with_xo_chip(true);
+    ch8state.display.draw_sprite(0, 5, &[0x80], false);
+    ch8state = ch8state.load(&[0x00,0xD2]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert!(ch8state.get_pixel(0, 3));
+    assert!(!ch8state.get_pixel(0, 5));
+}
+
+#[test]
+fn audio_instructions() {
+    let mut ch8state = Chip8State::new().with_xo_chip(true);
+    let pattern = [0xF0; 16];
+    ch8state.ram[0x300..0x310].copy_from_slice(&pattern);
+    ch8state.i = 0x300;
+    ch8state.reg[5] = 100;
+    ch8state = ch8state.load(&[0xF0,0x02,0xF5,0x3A]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.audio_pattern, pattern);
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.audio_pitch, 100);
+}
+
+#[test]
+fn save_all_flags_in_xo_chip_mode() {
+    let mut ch8state = Chip8State::new().with_xo_chip(true);
+    ch8state = ch8state.load(&[0xFF,0x75]);
+    ch8state.reg = [0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15];
+    ch8state = ch8state.exec_instruction().unwrap();
+    assert_eq!(ch8state.rpl, ch8state.reg);
+}
+
+
+fn snapshot(ch8state: &Chip8State) -> Vec<u8> {
+    let mut data = Vec::new();
+    ch8state.save_state(&mut data).unwrap();
+    data
+}
+
+#[test]
+fn snapshot_round_trip() {
+    let mut ch8state = Chip8State::new().with_quirks(Quirks::COSMAC_VIP).with_xo_chip(true);
+    // call 206; 206: draw, wait for the next frame, wait for key
+    ch8state = ch8state.load(&[0x22,0x06,0x00,0x00,0x00,0x00,0xD0,0x15,0xF3,0x0A]);
+    ch8state = ch8state.key_down(0xA);
+    for _ in 0..2 {
+        ch8state = ch8state.exec_instruction().unwrap();
+    }
+    let waiting: Chip8State = Chip8State::load_state(&snapshot(&ch8state)[..]).unwrap();
+    assert!(waiting.is_waiting_for_vblank());
+    ch8state = ch8state.vblank();
+    ch8state.t_delay = 30;
+    ch8state = ch8state.exec_instruction().unwrap();
+    let data = snapshot(&ch8state);
+    let restored: Chip8State = Chip8State::load_state(&data[..]).unwrap();
+    assert_eq!(&restored.ram[..], &ch8state.ram[..]);
+    assert_eq!(restored.pc, ch8state.pc);
+    assert_eq!(restored.sp, ch8state.sp);
+    assert_eq!(restored.t_delay, 30);
+    assert_eq!(restored.waiting_for_key, Some(3));
+    assert_eq!(restored.quirks, Quirks::COSMAC_VIP);
+    assert!(restored.xo_chip);
+    assert!(restored.is_key_down(0xA));
+    assert_eq!(restored.get_pixel(0, 0), ch8state.get_pixel(0, 0));
+    assert_eq!(snapshot(&restored), data);
+}
+
+#[test]
+fn snapshot_holds_memory_of_the_mode() {
+    let plain = snapshot(&Chip8State::new());
+    let xo_chip = snapshot(&Chip8State::new().with_xo_chip(true));
+    assert_eq!(xo_chip.len() - plain.len(), 0x10000 - 0x1000);
+}
+
+#[test]
+fn snapshot_rejects_other_data() {
+    match Chip8State::<BuiltinRandom>::load_state(&b"not a snapshot"[..]) {
+        Err(SnapshotError::NotASnapshot) => {},
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn snapshot_rejects_other_versions() {
+    let mut data = snapshot(&Chip8State::new());
+    data[5] = 99;
+    match Chip8State::<BuiltinRandom>::load_state(&data[..]) {
+        Err(SnapshotError::UnsupportedVersion(99)) => {},
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn snapshot_rejects_corrupt_data() {
+    let data = snapshot(&Chip8State::new());
+    let mut flipped = data.clone();
+    flipped[0x300] ^= 0x01;
+    for corrupt in &[&data[..data.len() - 1], &data[..100], &flipped[..]] {
+        match Chip8State::<BuiltinRandom>::load_state(*corrupt) {
+            Err(SnapshotError::Corrupt(_)) => {},
+            other => panic!("unexpected result: {:?}", other.map(|_| ())),
+        }
+    }
+}
+
+#[test]
+fn snapshot_keeps_random_sequence() {
+    // 200: V0 := random; 202: jump 200
+    let mut ch8state = Chip8State::with_random(BuiltinRandom::xorshift(99)).load(&[0xC0, 0xFF, 0x12, 0x00]);
+    ch8state = ch8state.exec_instruction().unwrap();
+    let restored: Chip8State = Chip8State::load_state(&snapshot(&ch8state)[..]).unwrap();
+    let mut original = ch8state;
+    let mut restored = restored;
+    for _ in 0..8 {
+        original = original.exec_instruction().unwrap();
+        restored = restored.exec_instruction().unwrap();
+        assert_eq!(restored.reg[0], original.reg[0]);
+    }
+}
+
+#[test]
+fn screen_as_text() {
+    let mut ch8state = Chip8State::new();
+    ch8state.display.draw_sprite(1, 0, &[0xA0, 0x40], false);
+    let text = ch8state.screen_to_text();
+    let lines: Vec<&str> = text.lines().collect();
+    assert_eq!(lines.len(), 32);
+    assert_eq!(&lines[0][..5], ".#.#.");
+    assert_eq!(&lines[1][..5], "..#..");
+    assert_eq!(lines[2], ".".repeat(64));
+}
+
+#[test]
+fn display_rows() {
+    let mut ch8state = Chip8State::new();
+    ch8state.display.draw_sprite(1, 2, &[0xA0], false);
+    assert_eq!(ch8state.display_row(0, 2), 0b0101 << 124);
+    assert_eq!(ch8state.display_plane(0).len(), 32);
+    assert_eq!(ch8state.display_plane(0).iter().filter(|&&row| row != 0).count(), 1);
+}
@@ -1,21 +1,36 @@
-#![feature(slice_patterns)]
-#![feature(test)]
-
-extern crate byteorder;     // to read instructions as big_endian u16 words
 #[macro_use] extern crate chan;          // for easier timers
+extern crate chip8r;
 extern crate clap;          // to manage command line options and arguments
-extern crate rand;          // to generate random numbers
 extern crate termion;       // to display screen on terminal
-extern crate test;
-
-mod chip8;
 
-use clap::{Arg, App};
-use chip8::Chip8State;
+use chip8r::chip8;
+use clap::{Arg, App, AppSettings, SubCommand};
+use chip8::{AudioSink, Beep, BellSink, BuiltinRandom, Chip8Error, Chip8State, FrameScheduler, Movie, PcmSink, Quirks,
+            Rewind, WavSink, instructions_in_frame};
+use chip8::debugger::Debugger;
+use chip8::disasm::MAX_ROM_SIZE;
+use chip8::termui::{Frame, Persistence, Pixels, RenderMode, Theme};
+use termion::event::Key;
+use termion::raw::IntoRawMode;
 use std::error::Error;
 use std::fs::File;
+use std::io;
 use std::io::prelude::*;
+use std::process;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+// Frames rewound for each Backspace key event, which repeat while it is held
+const REWIND_FRAMES_PER_KEY: usize = 3;
+// Time the machine stays stopped after a Backspace key event, so it doesn't run
+// between key repeats
+const REWIND_PAUSE_MS: u64 = 200;
+// Time the throttle stays off after a Tab key event, so fast-forward lasts between key repeats
+const FAST_FORWARD_MS: u64 = 200;
+// Frames per second, the rate of the timers and the display
+const FRAME_RATE: u32 = 60;
 
 fn main() {
     // Define command line arguments.
@@ -23,6 +38,7 @@ fn main() {
         .version("0.1.0")
         .author("Manuel Franco <mafraba@gmail.com>")
         .about("Run CHIP-8 programs in all their glory")
+        .setting(AppSettings::SubcommandsNegateReqs)
         .arg(Arg::with_name("file.ch8")
                 .short("i")
                 .long("input")
@@ -36,62 +52,452 @@ fn main() {
                 .default_value("500")
                 .validator(validate_clock_speed)
                 .help("Clock speed (in hertzs)"))
+        .arg(Arg::with_name("ipf")
+                .long("ipf")
+                .takes_value(true)
+                .validator(validate_instructions_per_frame)
+                .help("Instructions run per 60Hz frame (overrides --speed)"))
+        .arg(Arg::with_name("unthrottled")
+                .long("unthrottled")
+                .help("Run as fast as possible instead of in real time (hold Tab to do it for a while)"))
+        .arg(Arg::with_name("quirks")
+                .short("q")
+                .long("quirks")
+                .takes_value(true)
+                .default_value("modern")
+                .possible_values(Quirks::PRESET_NAMES)
+                .help("Behaviour of ambiguous instructions"))
+        .arg(Arg::with_name("xochip")
+                .short("x")
+                .long("xochip")
+                .help("Enable XO-CHIP extensions (implies '--quirks xochip' unless given)"))
+        .arg(Arg::with_name("edges")
+                .long("edges")
+                .takes_value(true)
+                .possible_values(&["clip", "wrap"])
+                .help("Whether sprites are clipped at the screen edges or wrap around (overrides --quirks)"))
+        .arg(Arg::with_name("key_wait")
+                .long("key-wait")
+                .takes_value(true)
+                .possible_values(&["press", "release"])
+                .help("Whether Fx0A goes on when a key is pressed or once it's released (overrides --quirks)"))
+        .arg(Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .validator(validate_count)
+                .help("Seed for the random number generator, to make runs reproducible"))
+        .arg(Arg::with_name("rng")
+                .long("rng")
+                .takes_value(true)
+                .default_value("xorshift")
+                .possible_values(BuiltinRandom::NAMES)
+                .help("Random number generator"))
+        .arg(Arg::with_name("debug")
+                .short("d")
+                .long("debug")
+                .help("Start paused in the interactive debugger"))
+        .arg(Arg::with_name("break")
+                .short("b")
+                .long("break")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .validator(validate_address)
+                .requires("debug")
+                .help("Breakpoint address (hexadecimal), can be repeated"))
+        .arg(Arg::with_name("rewind")
+                .long("rewind")
+                .takes_value(true)
+                .default_value("10")
+                .validator(validate_rewind_seconds)
+                .help("Seconds of history kept for rewinding (hold Backspace), 0 to disable"))
+        .arg(Arg::with_name("render")
+                .long("render")
+                .takes_value(true)
+                .default_value("block")
+                .possible_values(RenderMode::NAMES)
+                .help("How pixels are drawn: a block each, two per half block (square), or 2x4 per braille character"))
+        .arg(Arg::with_name("persistence")
+                .long("persistence")
+                .takes_value(true)
+                .default_value("off")
+                .possible_values(Persistence::NAMES)
+                .help("Against flicker, keep pixels turned off shown for a frame (or), or fading out (phosphor)"))
+        .arg(Arg::with_name("theme")
+                .long("theme")
+                .takes_value(true)
+                .default_value("terminal")
+                .possible_values(Theme::NAMES)
+                .help("Colours: the terminal's, green or amber phosphor, LCD, or high contrast"))
+        .arg(Arg::with_name("fg")
+                .long("fg")
+                .takes_value(true)
+                .validator(validate_rgb)
+                .help("Colour of the pixels, as RRGGBB (overrides --theme)"))
+        .arg(Arg::with_name("bg")
+                .long("bg")
+                .takes_value(true)
+                .validator(validate_rgb)
+                .help("Colour behind the pixels, as RRGGBB (overrides --theme)"))
+        .arg(Arg::with_name("headless")
+                .long("headless")
+                .help("Run without terminal UI nor keyboard, as fast as possible, then print the screen"))
+        .arg(Arg::with_name("instructions")
+                .long("instructions")
+                .takes_value(true)
+                .requires("headless")
+                .conflicts_with("frames")
+                .validator(validate_count)
+                .help("Instructions to run in headless mode"))
+        .arg(Arg::with_name("frames")
+                .long("frames")
+                .takes_value(true)
+                .requires("headless")
+                .validator(validate_count)
+                .help("60Hz frames to run in headless mode (default 600, or the movie length)"))
+        .arg(Arg::with_name("record")
+                .long("record")
+                .takes_value(true)
+                .conflicts_with_all(&["headless", "debug"])
+                .help("Record the keys pressed to a movie file, to replay it later (Ctrl-C stops)"))
+        .arg(Arg::with_name("replay")
+                .long("replay")
+                .takes_value(true)
+                .requires("headless")
+                .help("Replay a movie file in headless mode, with the settings it was recorded with"))
+        .arg(Arg::with_name("screen")
+                .long("screen")
+                .takes_value(true)
+                .requires("headless")
+                .help("File to write the final screen to in headless mode, instead of printing it"))
+        .arg(Arg::with_name("wav")
+                .long("wav")
+                .takes_value(true)
+                .conflicts_with_all(&["pcm", "bell"])
+                .help("Write the sound to a WAV file"))
+        .arg(Arg::with_name("pcm")
+                .long("pcm")
+                .takes_value(true)
+                .conflicts_with("bell")
+                .help("Write the sound as raw PCM (16-bit signed little-endian, mono, 44100Hz) to a file or FIFO, \
+                       or to stdout with '-' (headless mode with --screen only)"))
+        .arg(Arg::with_name("bell")
+                .long("bell")
+                .help("Ring the terminal bell when the sound starts"))
+        .arg(Arg::with_name("beep_frequency")
+                .long("beep-frequency")
+                .takes_value(true)
+                .default_value("440")
+                .validator(validate_beep_frequency)
+                .help("Frequency of the beep (in hertzs)"))
+        .arg(Arg::with_name("volume")
+                .long("volume")
+                .takes_value(true)
+                .default_value("50")
+                .validator(validate_volume)
+                .help("Volume of the beep, from 0 to 100"))
+        .subcommand(SubCommand::with_name("disasm")
+                .about("Disassemble a CHIP-8 program")
+                .arg(Arg::with_name("ROM")
+                        .required(true)
+                        .index(1)
+                        .help("CHIP-8 program to disassemble"))
+                .arg(Arg::with_name("quirks")
+                        .short("q")
+                        .long("quirks")
+                        .takes_value(true)
+                        .default_value("modern")
+                        .possible_values(Quirks::PRESET_NAMES)
+                        .help("Behaviour of ambiguous instructions, for how they are shown")))
+        .subcommand(SubCommand::with_name("asm")
+                .about("Assemble a program written in Octo syntax")
+                .arg(Arg::with_name("SOURCE")
+                        .required(true)
+                        .index(1)
+                        .help("Octo source file"))
+                .arg(Arg::with_name("output")
+                        .short("o")
+                        .long("output")
+                        .takes_value(true)
+                        .required(true)
+                        .help("CHIP-8 program to write")))
         .get_matches();
 
+    // Subcommands
+    if let Some(disasm_matches) = matches.subcommand_matches("disasm") {
+        let rom = read_rom(disasm_matches.value_of("ROM").unwrap());
+        let quirks = Quirks::from_name(disasm_matches.value_of("quirks").unwrap()).unwrap();
+        print!("{}", chip8::disasm::disassemble(&rom, &quirks));
+        return;
+    }
+    if let Some(asm_matches) = matches.subcommand_matches("asm") {
+        assemble(asm_matches.value_of("SOURCE").unwrap(), asm_matches.value_of("output").unwrap());
+        return;
+    }
+
     // Get input file
     let input_file = matches.value_of("file.ch8").unwrap();
     let clock_speed_str = matches.value_of("clock_speed").unwrap();
-    let clock_speed = clock_speed_str.parse::<u32>().unwrap();
+    let mut clock_speed = clock_speed_str.parse::<u32>().unwrap();
+    let mut xo_chip = matches.is_present("xochip");
+    let rewind_seconds = matches.value_of("rewind").unwrap().parse::<usize>().unwrap();
+    let mut quirks = if xo_chip && matches.occurrences_of("quirks") == 0 {
+        Quirks::XO_CHIP
+    } else {
+        Quirks::from_name(matches.value_of("quirks").unwrap()).unwrap()
+    };
+    if let Some(edges) = matches.value_of("edges") {
+        quirks.wrap_sprites = edges == "wrap";
+    }
+    if let Some(key_wait) = matches.value_of("key_wait") {
+        quirks.key_wait_release = key_wait == "release";
+    }
+    let seed = match matches.value_of("seed") {
+        Some(seed_str) => seed_str.parse::<u64>().unwrap(),
+        None => time_seed(),
+    };
+    let rng_name = *BuiltinRandom::NAMES.iter().find(|&&name| name == matches.value_of("rng").unwrap()).unwrap();
+    let mut rng = BuiltinRandom::from_name(rng_name, seed).unwrap();
 
     // Read it
-    let mut ch8_file = match File::open(input_file) {
-        Err(why) => panic!("couldn't open input file '{}': {}", input_file, why.description()),
-        Ok(file) => file,
+    let ch8_buffer = read_rom(input_file);
+
+    // A replayed movie brings the settings it was recorded with
+    let replay = matches.value_of("replay").map(|path| read_movie(path, &ch8_buffer));
+    if let Some(ref movie) = replay {
+        clock_speed = movie.clock_speed;
+        xo_chip = movie.xo_chip;
+        quirks = movie.quirks;
+        rng = movie.random_source();
+    }
+    // Programs are loaded at 0x200, and must fit in the memory of the chosen mode
+    let rom_limit = if xo_chip { MAX_ROM_SIZE } else { 0x1000 - 0x200 };
+    if ch8_buffer.len() > rom_limit {
+        panic!("ROM '{}' is too large: {} bytes, at most {} fit{}", input_file, ch8_buffer.len(), rom_limit,
+               if xo_chip { "" } else { " (more with --xochip)" });
+    }
+    if let (Some(n), None) = (matches.value_of("ipf"), replay.as_ref()) {
+        clock_speed = n.parse::<u32>().unwrap() * FRAME_RATE;
+    }
+
+    // Sound output, if any
+    let beep = Beep {
+        frequency: matches.value_of("beep_frequency").unwrap().parse::<u32>().unwrap(),
+        volume: matches.value_of("volume").unwrap().parse::<u8>().unwrap(),
     };
-    let mut ch8_buffer = Vec::new();
-    ch8_file.read_to_end(&mut ch8_buffer).expect("Failed to read ROM");
+    let mut audio: Option<Box<dyn AudioSink>> = if let Some(path) = matches.value_of("wav") {
+        match File::create(path).and_then(|f| WavSink::new(f, beep)) {
+            Ok(sink) => Some(Box::new(sink)),
+            Err(why) => panic!("couldn't create WAV file '{}': {}", path, why),
+        }
+    } else if let Some(path) = matches.value_of("pcm") {
+        if path != "-" {
+            match File::create(path) {
+                Ok(f) => Some(Box::new(PcmSink::new(f, beep))),
+                Err(why) => panic!("couldn't create PCM file '{}': {}", path, why),
+            }
+        } else if matches.is_present("headless") && matches.is_present("screen") {
+            Some(Box::new(PcmSink::new(io::stdout(), beep)))
+        } else {
+            // the screen is printed on stdout otherwise
+            eprintln!("'--pcm -' requires --headless and --screen");
+            process::exit(1);
+        }
+    } else if matches.is_present("bell") {
+        // stderr, so the bell doesn't get mixed with the screen drawn on stdout
+        Some(Box::new(BellSink::new(io::stderr())))
+    } else {
+        None
+    };
+
+    // Create runtime and load file to memory
+    let new_machine = || Chip8State::with_random(rng).with_quirks(quirks).with_xo_chip(xo_chip).load(&ch8_buffer);
+    let mut ch8state = new_machine();
+
+    if matches.is_present("headless") {
+        let default_frames = replay.as_ref().map_or(600, |movie| movie.frames as u64);
+        let (frames, instructions) = match matches.value_of("instructions") {
+            Some(n) => (u64::MAX, Some(n.parse::<u64>().unwrap())),
+            None => (matches.value_of("frames").map_or(default_frames, |n| n.parse::<u64>().unwrap()), None),
+        };
+        run_headless(ch8state, clock_speed, frames, instructions, replay.as_ref(), matches.value_of("screen"), &mut audio);
+        return;
+    }
 
-    // Create runtime
-    let mut ch8state = Chip8State::new();
-
-    // Load file to memory
-    ch8state = ch8state.load(&ch8_buffer);
-
-    // Create timer to execution of instruction
-    let tick = chan::tick_ms(1000/clock_speed);
-    // Create timer thread for decreasing delay and sound timers
-    let timers_decrease = chan::tick_ms(1000/60); // ~ 60Hz
-    // Create timer thread for refreshing display
-    let display_refresh_timer = chan::tick_ms(1000/15); // ~ 15Hz
-    // Create display thread so we can just send the state and continue this main thread
-    let (tx_display, rx_display) = chan::async();
-    thread::spawn(|| chip8::termui::display_loop(rx_display));
+    // Keys pressed so far, if recording
+    let mut recording = matches.value_of("record").map(|path| {
+        (path, Movie::new(&ch8_buffer, clock_speed, seed, rng_name, quirks, xo_chip))
+    });
+    // Frames run so far
+    let mut frame: u32 = 0;
+    // Whether the sound timer was active in the last frame
+    let mut beeping = false;
+    // Whether frames run in real time, and until when fast-forward (Tab) lasts otherwise
+    let unthrottled = matches.is_present("unthrottled");
+    let throttled = Arc::new(AtomicBool::new(!unthrottled));
+    let mut fast_forward_until = Instant::now();
+
+    // Error that stopped the execution, if any
+    let mut crash = None;
+    // Result of the last snapshot hotkey, shown under the screen
+    let mut message = None;
+    // History of states, one per frame
+    let mut rewind = Rewind::new(rewind_seconds * 60);
+    // While rewinding, the machine is stopped
+    let mut rewinding_until = Instant::now();
+    // Debugger, if enabled
+    let mut debugger = if matches.is_present("debug") {
+        let breakpoints: Vec<u16> = matches.values_of("break").map_or(Vec::new(), |addrs| {
+            addrs.map(|addr| u16::from_str_radix(addr, 16).unwrap()).collect()
+        });
+        Some(Debugger::new(&breakpoints))
+    } else {
+        None
+    };
+
+    // Create timer for running frames: a batch of instructions, then decreasing delay and sound
+    // timers, then refreshing the display
+    let frame_tick = frame_ticker(throttled.clone());
+    // The terminal stays in raw mode for the whole session, until this is dropped on return
+    let _raw_terminal = io::stdout().into_raw_mode().unwrap();
+    // Create display thread so we can just send the state and continue this main thread.
+    // It holds one frame at most: frames are skipped if the terminal can't keep up
+    let (tx_display, rx_display) = chan::sync(1);
+    let render_mode = RenderMode::from_name(matches.value_of("render").unwrap()).unwrap();
+    let mut theme = Theme::from_name(matches.value_of("theme").unwrap()).unwrap();
+    if let Some(rgb) = matches.value_of("fg") {
+        theme = theme.with_foreground(Theme::parse_rgb(rgb).unwrap());
+    }
+    if let Some(rgb) = matches.value_of("bg") {
+        theme = theme.with_background(Theme::parse_rgb(rgb).unwrap());
+    }
+    let persistence = Persistence::from_name(matches.value_of("persistence").unwrap()).unwrap();
+    // The screen as shown, updated here for every frame run since some may not be drawn
+    let mut pixels = Pixels::new();
+    thread::spawn(move || chip8::termui::display_loop(rx_display, render_mode, theme));
     // Create thread to read keyboard events
     let (tx_keys, rx_keys) = chan::async();
     thread::spawn(|| chip8::termui::listen_for_keys(tx_keys));
 
     loop {
         chan_select! {
-            tick.recv() => {
-                ch8state = ch8state.exec_instruction();
-            },
-            timers_decrease.recv() => {
-                ch8state = ch8state.decrease_timers();
-            },
-            display_refresh_timer.recv() => {
-                tx_display.send(ch8state);
+            frame_tick.recv() => {
+                throttled.store(!unthrottled && Instant::now() >= fast_forward_until, Ordering::Relaxed);
+                if crash.is_none() && Instant::now() >= rewinding_until {
+                    let instructions = instructions_in_frame(clock_speed, FRAME_RATE, frame as u64);
+                    match run_frame(&mut ch8state, instructions, &mut debugger) {
+                        Ok(_) => {
+                            // the program asked to exit (00FD)
+                            if ch8state.is_halted() {
+                                finish_recording(recording, frame);
+                                finish_audio(&mut audio);
+                                return;
+                            }
+                            if !debugger.as_ref().is_some_and(|d| d.is_paused()) {
+                                play_frame(&mut audio, &ch8state, &mut beeping);
+                                pixels.update(&ch8state, persistence);
+                                ch8state.end_frame();
+                                if rewind_seconds > 0 {
+                                    rewind.push(&ch8state);
+                                }
+                                frame += 1;
+                            }
+                        },
+                        Err(e) => {
+                            crash = Some(e);
+                            if let Some(ref mut d) = debugger {
+                                d.pause();
+                            }
+                        },
+                    }
+                }
+                pixels.refresh(&ch8state);
+                let display_frame = Frame { state: ch8state.clone(), pixels: pixels.clone(), error: crash,
+                                            debugger: debugger.clone(), message: message.clone() };
+                chan_select! {
+                    default => {},
+                    tx_display.send(display_frame) => {},
+                }
             },
             rx_keys.recv() -> key => {
+                let key = match key.unwrap() {
+                    Key::Ctrl('c') => {
+                        finish_recording(recording, frame);
+                        finish_audio(&mut audio);
+                        return;
+                    },
+                    // going back in time or restarting would break the recorded movie
+                    Key::F(5..=8) | Key::Backspace if recording.is_some() => {
+                        message = Some(String::from("Not available while recording"));
+                        continue;
+                    },
+                    Key::Char('r') if recording.is_some() && crash.is_some() => {
+                        message = Some(String::from("Not available while recording"));
+                        continue;
+                    },
+                    // F1-F4 save a snapshot to slots 1-4, F5-F8 restore them
+                    Key::F(n @ 1..=4) => {
+                        message = Some(save_snapshot(&ch8state, input_file, n));
+                        continue;
+                    },
+                    Key::F(n @ 5..=8) => {
+                        match load_snapshot(input_file, n - 4) {
+                            Ok(st) => {
+                                ch8state = st;
+                                crash = None;
+                                rewind.clear();
+                                message = Some(format!("Loaded slot {}", n - 4));
+                            },
+                            Err(e) => message = Some(e),
+                        }
+                        continue;
+                    },
+                    // each key repeat goes back a few frames, stopping the machine for a while
+                    Key::Backspace => {
+                        if let Some(st) = rewind.step_back(REWIND_FRAMES_PER_KEY) {
+                            ch8state = st;
+                            crash = None;
+                        }
+                        rewinding_until = Instant::now() + Duration::from_millis(REWIND_PAUSE_MS);
+                        continue;
+                    },
+                    // each key repeat keeps the throttle off for a while
+                    Key::Char('\t') => {
+                        fast_forward_until = Instant::now() + Duration::from_millis(FAST_FORWARD_MS);
+                        throttled.store(false, Ordering::Relaxed);
+                        continue;
+                    },
+                    Key::Char(c) => c,
+                    Key::Esc => '\x1b',
+                    _ => continue,
+                };
+                // 'r' restarts the program once crashed
+                if crash.is_some() && key == 'r' {
+                    ch8state = new_machine();
+                    crash = None;
+                    rewind.clear();
+                    continue;
+                }
+                // debugger commands
+                if let Some(ref mut d) = debugger {
+                    if d.key_pressed(key, &ch8state) {
+                        continue;
+                    }
+                }
                 // if hexadecimal digit
-                match key.unwrap().to_digit(16) {
+                match key.to_digit(16) {
                     Some(digit) => {
                         let d = digit as u8;
                         // since no key-release events are received,
                         // need to simulate it with a new press
-                        if ch8state.is_key_down(d) {
-                            ch8state = ch8state.key_up(d);
+                        let pressed = !ch8state.is_key_down(d);
+                        if pressed {
+                            ch8state.press_key(d);
                         } else {
-                            ch8state = ch8state.key_down(d);
+                            ch8state.release_key(d);
+                        }
+                        if let Some((_, ref mut movie)) = recording {
+                            movie.record(frame, d, pressed);
                         }
                     },
                     None => {}
@@ -101,16 +507,266 @@ fn main() {
     }
 }
 
+// Read a whole ROM file
+fn read_rom(input_file: &str) -> Vec<u8> {
+    let mut ch8_file = match File::open(input_file) {
+        Err(why) => panic!("couldn't open input file '{}': {}", input_file, why.description()),
+        Ok(file) => file,
+    };
+    let mut ch8_buffer = Vec::new();
+    ch8_file.read_to_end(&mut ch8_buffer).expect("Failed to read ROM");
+    if ch8_buffer.len() > MAX_ROM_SIZE {
+        panic!("ROM '{}' is too large: {} bytes, at most {} fit in memory", input_file, ch8_buffer.len(), MAX_ROM_SIZE);
+    }
+    ch8_buffer
+}
+
+// Ticks at FRAME_RATE, keeping real-time pace even if some frames come late. While not
+// 'throttled', it ticks as soon as the previous tick is taken
+fn frame_ticker(throttled: Arc<AtomicBool>) -> chan::Receiver<()> {
+    let (tx, rx) = chan::sync(0);
+    thread::spawn(move || {
+        let mut scheduler = FrameScheduler::new(FRAME_RATE, Instant::now());
+        loop {
+            if throttled.load(Ordering::Relaxed) {
+                thread::sleep(scheduler.wait_time(Instant::now()));
+            }
+            tx.send(());
+            scheduler.frame_done(Instant::now());
+        }
+    });
+    rx
+}
+
+// Run up to 'instructions' instructions of a frame, stopping early if the program exits,
+// waits for the next frame or the debugger stops it. Returns how many were run
+fn run_frame(ch8state: &mut Chip8State, instructions: u64, debugger: &mut Option<Debugger>) -> Result<u64, Chip8Error> {
+    for executed in 0..instructions {
+        let may_execute = match *debugger {
+            Some(ref mut d) => d.may_execute(ch8state),
+            None => true,
+        };
+        if ch8state.is_halted() || ch8state.is_waiting_for_vblank() || !may_execute {
+            return Ok(executed);
+        }
+        ch8state.step()?;
+    }
+    Ok(instructions)
+}
+
+// Run a number of frames (or of instructions, if given) as fast as possible, each made of
+// the instructions for the clock speed followed by a decrease of the timers, pressing the
+// keys of a movie if replaying one. Then print or write the screen, exiting with an error
+// status if the program crashed
+fn run_headless(mut ch8state: Chip8State, clock_speed: u32, frames: u64, instructions: Option<u64>,
+                movie: Option<&Movie>, screen_file: Option<&str>, audio: &mut Option<Box<dyn AudioSink>>) {
+    let mut crash = None;
+    let mut beeping = false;
+    let mut remaining = instructions.unwrap_or(u64::MAX);
+    let mut frame = 0;
+    let mut playback = movie.map(|movie| movie.playback());
+    while frame < frames && remaining > 0 && !ch8state.is_halted() {
+        if let Some(ref mut playback) = playback {
+            for event in playback.events_at(frame as u32) {
+                if event.pressed { ch8state.press_key(event.key) } else { ch8state.release_key(event.key) }
+            }
+        }
+        let per_frame = instructions_in_frame(clock_speed, FRAME_RATE, frame);
+        let n = ::std::cmp::min(per_frame, remaining);
+        match run_frame(&mut ch8state, n, &mut None) {
+            Ok(executed) => remaining -= executed,
+            Err(e) => {
+                crash = Some(e);
+                break;
+            }
+        }
+        // unless the instruction count ran out halfway through it, the frame is over
+        if n == per_frame || remaining > 0 {
+            play_frame(audio, &ch8state, &mut beeping);
+            ch8state.end_frame();
+        }
+        frame += 1;
+    }
+    finish_audio(audio);
+    let screen = ch8state.screen_to_text();
+    match screen_file {
+        Some(path) => {
+            if let Err(why) = File::create(path).and_then(|mut f| f.write_all(screen.as_bytes())) {
+                panic!("couldn't write screen file '{}': {}", path, why);
+            }
+        },
+        None => print!("{}", screen),
+    }
+    if let Some(e) = crash {
+        eprintln!("crashed: {}", e);
+        process::exit(1);
+    }
+}
+
+// Read a movie to replay, exiting with an error status if it can't be used with the ROM
+fn read_movie(path: &str, rom: &[u8]) -> Movie {
+    let movie = match File::open(path).map_err(|e| e.to_string())
+            .and_then(|f| Movie::load(f).map_err(|e| e.to_string())) {
+        Ok(movie) => movie,
+        Err(e) => {
+            eprintln!("couldn't load movie '{}': {}", path, e);
+            process::exit(1);
+        },
+    };
+    if !movie.matches_rom(rom) {
+        eprintln!("movie '{}' was recorded with another ROM", path);
+        process::exit(1);
+    }
+    movie
+}
+
+// Write the recorded movie, if recording, ending it at 'frame'
+fn finish_recording(recording: Option<(&str, Movie)>, frame: u32) {
+    if let Some((path, mut movie)) = recording {
+        movie.frames = frame;
+        if let Err(why) = File::create(path).and_then(|f| movie.save(f)) {
+            eprintln!("couldn't write movie file '{}': {}", path, why);
+        }
+    }
+}
+
+// Tell the audio sink, if any, how the sound timer was at the end of a frame. Sound stops
+// if the sink fails, rather than the whole program
+fn play_frame(audio: &mut Option<Box<dyn AudioSink>>, ch8state: &Chip8State, beeping: &mut bool) {
+    let result = match *audio {
+        Some(ref mut sink) => {
+            let changed = ch8state.is_beeping() != *beeping;
+            *beeping = ch8state.is_beeping();
+            if changed { sink.set_active(*beeping) } else { Ok(()) }.and_then(|_| sink.end_frame())
+        },
+        None => return,
+    };
+    if let Err(why) = result {
+        eprintln!("couldn't play sound: {}", why);
+        *audio = None;
+    }
+}
+
+// Complete the sound output, if any
+fn finish_audio(audio: &mut Option<Box<dyn AudioSink>>) {
+    if let Some(ref mut sink) = *audio {
+        if let Err(why) = sink.finish() {
+            eprintln!("couldn't write sound: {}", why);
+        }
+    }
+}
+
+// Path of a snapshot slot for the ROM
+fn snapshot_path(input_file: &str, slot: u8) -> String {
+    format!("{}.state{}", input_file, slot)
+}
+
+// Save a snapshot to a slot, returning a message telling how it went
+fn save_snapshot(ch8state: &Chip8State, input_file: &str, slot: u8) -> String {
+    let path = snapshot_path(input_file, slot);
+    match File::create(&path).and_then(|f| ch8state.save_state(f)) {
+        Ok(()) => format!("Saved slot {}", slot),
+        Err(e) => format!("Couldn't save '{}': {}", path, e),
+    }
+}
+
+// Load a snapshot from a slot
+fn load_snapshot(input_file: &str, slot: u8) -> Result<Chip8State, String> {
+    let path = snapshot_path(input_file, slot);
+    let file = File::open(&path).map_err(|e| format!("Couldn't open '{}': {}", path, e))?;
+    Chip8State::load_state(file).map_err(|e| format!("Couldn't load '{}': {}", path, e))
+}
+
+// Assemble an Octo source file into a CHIP-8 program, exiting with an error status if it fails
+fn assemble(source_file: &str, output_file: &str) {
+    let source = match File::open(source_file).and_then(|mut f| {
+        let mut source = String::new();
+        f.read_to_string(&mut source).map(|_| source)
+    }) {
+        Err(why) => panic!("couldn't read source file '{}': {}", source_file, why),
+        Ok(source) => source,
+    };
+    let rom = match chip8::asm::assemble(&source) {
+        Err(e) => {
+            eprintln!("{}:{}", source_file, e);
+            process::exit(1);
+        },
+        Ok(rom) => rom,
+    };
+    if let Err(why) = File::create(output_file).and_then(|mut f| f.write_all(&rom)) {
+        panic!("couldn't write output file '{}': {}", output_file, why);
+    }
+}
+
 fn validate_clock_speed(clock_speed_str: String) -> Result<(), String> {
     let clock_speed = clock_speed_str.parse::<u32>();
     match clock_speed {
         Ok(s) => {
-            if s>0 && s<=1000 {
+            if s>0 {
                 Ok(())
             } else {
-                Err(String::from("the clock speed must be lower than 1000Hz"))
+                Err(String::from("the clock speed must be at least 1Hz"))
             }
         },
         _ => Err(format!("not an integer value: {}", clock_speed_str))
     }
 }
+
+fn validate_instructions_per_frame(count_str: String) -> Result<(), String> {
+    match count_str.parse::<u32>() {
+        Ok(n) if n > 0 && n <= u32::MAX / FRAME_RATE => Ok(()),
+        Ok(0) => Err(String::from("at least one instruction per frame is needed")),
+        Ok(_) => Err(String::from("too many instructions per frame")),
+        _ => Err(format!("not an integer value: {}", count_str))
+    }
+}
+
+fn validate_beep_frequency(frequency_str: String) -> Result<(), String> {
+    match frequency_str.parse::<u32>() {
+        Ok(f) if f > 0 && f <= chip8::SAMPLE_RATE / 2 => Ok(()),
+        Ok(_) => Err(format!("the frequency must be between 1Hz and {}Hz", chip8::SAMPLE_RATE / 2)),
+        _ => Err(format!("not an integer value: {}", frequency_str))
+    }
+}
+
+fn validate_volume(volume_str: String) -> Result<(), String> {
+    match volume_str.parse::<u8>() {
+        Ok(v) if v <= 100 => Ok(()),
+        _ => Err(format!("not a volume from 0 to 100: {}", volume_str))
+    }
+}
+
+fn validate_rgb(rgb_str: String) -> Result<(), String> {
+    match Theme::parse_rgb(&rgb_str) {
+        Some(_) => Ok(()),
+        None => Err(format!("not a RRGGBB colour: {}", rgb_str))
+    }
+}
+
+fn validate_rewind_seconds(seconds_str: String) -> Result<(), String> {
+    match seconds_str.parse::<usize>() {
+        Ok(s) if s <= 600 => Ok(()),
+        Ok(_) => Err(String::from("the rewind history must be up to 600 seconds")),
+        _ => Err(format!("not an integer value: {}", seconds_str))
+    }
+}
+
+// Seed for runs without '--seed', different each time
+fn time_seed() -> u64 {
+    let elapsed = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or(Duration::from_secs(0));
+    elapsed.as_secs() ^ (elapsed.subsec_nanos() as u64) << 32
+}
+
+fn validate_count(count_str: String) -> Result<(), String> {
+    match count_str.parse::<u64>() {
+        Ok(_) => Ok(()),
+        _ => Err(format!("not an integer value: {}", count_str))
+    }
+}
+
+fn validate_address(addr_str: String) -> Result<(), String> {
+    match u16::from_str_radix(&addr_str, 16) {
+        Ok(_) => Ok(()),
+        _ => Err(format!("not a hexadecimal address: {}", addr_str))
+    }
+}